@@ -20,6 +20,7 @@ fn add_jitter(interval: u64) -> u64 {
 pub async fn run(wait_time: u64) {
     info!("Starting scheduler");
     loop {
+        let mut stopped: Vec<[u8; 20]> = Vec::new();
         let next_interval = {
             let list = TORRENTS.read().await;
             // Compute minimum time until next announce across all torrents
@@ -28,6 +29,21 @@ pub async fn run(wait_time: u64) {
                 let mut t = m.lock().await;
                 if t.should_announce() {
                     super::tracker::announce(&mut t, None).await;
+                    if t.should_stop() {
+                        info!("Stop rule of category reached: {}", t.name);
+                        crate::events::publish(crate::events::BusEvent::StopRuleReached {
+                            info_hash: crate::utils::to_hex(&t.info_hash),
+                            name: t.name.clone(),
+                            uploaded: t.uploaded,
+                        });
+                        stopped.push(t.info_hash);
+                    }
+                }
+                // it is not announced again, even if a tracker is left with `stop_torrent`
+                if !t.paused && t.is_unregistered() && !stopped.contains(&t.info_hash) {
+                    info!("Torrent not registered on its trackers: {}", t.name);
+                    stopped.push(t.info_hash);
+                    continue;
                 }
                 // Always update min_interval based on time until next announce
                 let elapsed = t.last_announce.elapsed().as_secs();
@@ -41,8 +57,14 @@ pub async fn run(wait_time: u64) {
                 add_jitter(min_interval)
             }
         };
+        // the torrent list must not be locked to pause or remove torrents
+        for info_hash in stopped {
+            crate::watcher::finish_torrent(&info_hash).await;
+        }
         debug!("Next announce in {}s", next_interval);
-        crate::json_output::write().await;
+        crate::json_output::write(false).await;
+        crate::history::prune();
+        crate::history::save(false).await;
         tokio::time::sleep(Duration::from_secs(next_interval)).await;
     }
 }
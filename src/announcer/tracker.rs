@@ -1,10 +1,13 @@
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use crate::bencode::{BencodeDecoder, BencodeValue};
+use crate::bencode::from_bytes_with_limits;
+use crate::events::{self, BusEvent};
 use crate::torrent::Torrent;
-use crate::{CLIENT, CONFIG, TORRENTS};
+use crate::utils::{percent_encoding, to_hex};
+use crate::{CATEGORY_CLIENTS, CLIENT, CONFIG, TORRENTS};
 use fake_torrent_client::Client;
 use reqwest::Client as ReqwestClient;
+use serde::{Deserialize, Serialize};
 use tracing::{debug, error, info, warn};
 use url::{Host, Url};
 
@@ -25,6 +28,40 @@ use url::{Host, Url};
 //     }
 // }
 
+/// Result of the last announce to a tracker, for the stats
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct TrackerStatus {
+    pub last_announce: Option<chrono::DateTime<chrono::Utc>>,
+    /// `None` if the last announce succeeded
+    pub last_error: Option<String>,
+    /// The tracker told that the torrent is not registered, it is not announced anymore
+    pub unregistered: bool,
+}
+
+/// Response of an HTTP tracker, other keys (i.e. `peers`) are ignored
+#[derive(Debug, Default, Deserialize, PartialEq)]
+struct AnnounceResponse<'a> {
+    /// If present, then no other keys may be present. The value is a human-readable error message as to why the
+    /// request failed
+    #[serde(rename = "failure reason", borrow)]
+    failure_reason: Option<&'a [u8]>,
+    /// The response still gets processed normally
+    #[serde(rename = "warning message", borrow)]
+    warning_message: Option<&'a [u8]>,
+    /// Interval in seconds that the client should wait between sending regular requests to the tracker
+    interval: Option<u64>,
+    /// Minimum announce interval. If present clients must not reannounce more frequently than this.
+    #[serde(rename = "min interval")]
+    min_interval: Option<u64>,
+    /// A string that the client should send back on its next announcements
+    #[serde(rename = "tracker id", alias = "tracker_id", borrow)]
+    tracker_id: Option<&'a [u8]>,
+    /// Number of peers with the entire file, i.e. seeders
+    complete: Option<u64>,
+    /// Number of leechers
+    incomplete: Option<u64>,
+}
+
 /// The optional announce event.
 #[repr(u8)]
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -38,12 +75,64 @@ pub enum Event {
     Stopped,
 }
 
+impl Event {
+    /// Value of the `event` parameter in the announce query
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Event::Started => "started",
+            // Event::Completed => "completed",
+            Event::Stopped => "stopped",
+        }
+    }
+}
+
+/// Why an announce to a tracker failed
+#[derive(Debug)]
+pub enum AnnounceError {
+    /// Cannot reach the tracker (DNS, connection, ...)
+    Network(String),
+    Timeout,
+    /// The response cannot be understood
+    InvalidResponse(String),
+    /// The tracker returned a `failure reason` or an error message
+    Tracker(String),
+    InvalidUrl,
+}
+
+impl AnnounceError {
+    /// Short name of the error, used for metrics labels
+    pub fn kind(&self) -> &'static str {
+        match self {
+            AnnounceError::Network(_) => "network",
+            AnnounceError::Timeout => "timeout",
+            AnnounceError::InvalidResponse(_) => "invalid_response",
+            AnnounceError::Tracker(_) => "tracker",
+            AnnounceError::InvalidUrl => "invalid_url",
+        }
+    }
+}
+
+impl std::fmt::Display for AnnounceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AnnounceError::Network(e) => write!(f, "network error: {e}"),
+            AnnounceError::Timeout => write!(f, "connection timed out"),
+            AnnounceError::InvalidResponse(e) => write!(f, "invalid response: {e}"),
+            AnnounceError::Tracker(msg) => write!(f, "tracker error: {msg}"),
+            AnnounceError::InvalidUrl => write!(f, "invalid tracker URL"),
+        }
+    }
+}
+
 pub async fn announce_started() -> u64 {
     info!("Announcing torrent(s) with STARTED event");
     let list = TORRENTS.read().await;
     let mut wait_time = u64::MAX;
     for m in list.iter() {
         let mut t = m.lock().await;
+        if t.paused {
+            continue;
+        }
         announce(&mut t, Some(Event::Started)).await;
         wait_time = wait_time.min(t.interval);
         info!("Time: {}", wait_time);
@@ -58,7 +147,10 @@ pub async fn announce_stopped() {
 
     for m in list.iter() {
         let mut t = m.lock().await;
-        announce(&mut t, Some(Event::Stopped)).await;
+        // paused torrents were already stopped
+        if !t.paused {
+            announce(&mut t, Some(Event::Stopped)).await;
+        }
         total_uploaded += t.uploaded;
         info!(
             "Torrent \"{}\": uploaded={}, seeders={}, leechers={}, errors={}",
@@ -133,17 +225,107 @@ pub fn is_supported_url(url_str: &str) -> bool {
 /// returned in the first announce response.
 pub async fn announce(torrent: &mut Torrent, event: Option<Event>) {
     // TODO: prepare announce (uploaded and downloaded if applicable)
-    torrent.compute_speeds();
-    if let Some(client) = &*CLIENT.read().await {
+    torrent.compute_speeds().await;
+    if let Some(client) = &client_for(torrent).await {
         debug!("Torrent has {} url(s)", torrent.urls.len());
+        let info_hash = to_hex(&torrent.info_hash);
+        events::publish(BusEvent::AnnounceStarted {
+            info_hash: info_hash.clone(),
+            name: torrent.name.clone(),
+            event: event.map(|e| e.as_str()),
+        });
+        let mut last_error = None;
+        let mut newly_unregistered = false;
         for url in torrent.urls.clone() {
+            if torrent.trackers.get(&url).is_some_and(|s| s.unregistered) {
+                debug!("\t{url} skipped, the torrent is not registered");
+                continue;
+            }
             debug!("\t{}", url);
-            if url.to_lowercase().starts_with("udp://") {
-                crate::announcer::udp::announce_udp(&url, torrent, client, event).await;
-            } else {
-                announce_http(&url, torrent, client, event).await;
+            let mut url_error = None;
+            let mut unregistered = false;
+            // the last announce to this tracker succeeded, or it was never announced
+            let was_ok = torrent
+                .trackers
+                .get(&url)
+                .is_none_or(|s| s.last_error.is_none());
+            for (i, hash) in torrent.announce_hashes().iter().enumerate() {
+                let (seeders, leechers) = (torrent.seeders, torrent.leechers);
+                let started = Instant::now();
+                let result = if url.to_lowercase().starts_with("udp://") {
+                    crate::announcer::udp::announce_udp(&url, torrent, hash, client, event).await
+                } else {
+                    announce_http(&url, torrent, hash, client, event).await
+                };
+                crate::metrics::record_announce(&url, started.elapsed(), &result);
+                // the upload of an hybrid torrent is only counted once
+                if i == 0
+                    && let Ok(uploaded) = result
+                {
+                    crate::history::record(
+                        &info_hash,
+                        &crate::metrics::tracker_host(&url),
+                        uploaded,
+                        torrent.next_upload_speed,
+                        torrent.seeders,
+                        torrent.leechers,
+                    );
+                }
+                if i > 0 {
+                    // the v1 and v2 swarms of an hybrid torrent share most of their peers
+                    torrent.seeders = torrent.seeders.max(seeders);
+                    torrent.leechers = torrent.leechers.max(leechers);
+                }
+                if let Err(AnnounceError::Tracker(reason)) = &result
+                    && CONFIG.get().is_some_and(|c| {
+                        c.is_unregistered(&crate::metrics::tracker_host(&url), reason)
+                    })
+                {
+                    unregistered = true;
+                }
+                if let Err(e) = result {
+                    error!("Cannot announce to {url}: {e}");
+                    torrent.error_count += 1;
+                    events::publish(BusEvent::TrackerError {
+                        info_hash: info_hash.clone(),
+                        name: torrent.name.clone(),
+                        host: crate::metrics::tracker_host(&url),
+                        kind: e.kind(),
+                        message: e.to_string(),
+                        error_count: torrent.error_count,
+                        first: was_ok && url_error.is_none(),
+                        unregistered,
+                    });
+                    url_error = Some(e.to_string());
+                }
+            }
+            if url_error.is_some() {
+                last_error.clone_from(&url_error);
+            }
+            if unregistered {
+                newly_unregistered = true;
+                warn!("Torrent {} is not registered on {url}", torrent.name);
+                events::publish(BusEvent::TorrentUnregistered {
+                    info_hash: info_hash.clone(),
+                    name: torrent.name.clone(),
+                    host: crate::metrics::tracker_host(&url),
+                    message: url_error.clone().unwrap_or_default(),
+                });
             }
+            torrent.trackers.insert(
+                url,
+                TrackerStatus {
+                    last_announce: Some(chrono::Utc::now()),
+                    last_error: url_error,
+                    unregistered,
+                },
+            );
         }
+        torrent.last_error = last_error;
+        if newly_unregistered {
+            torrent.save_unregistered();
+        }
+        events::publish(BusEvent::announce_completed(torrent));
         info!(
             "Anounced: interval={}, event={:?}, downloaded=0, uploaded={}, seeders={}, leechers={}, torrent={}",
             torrent.interval,
@@ -156,6 +338,26 @@ pub async fn announce(torrent: &mut Torrent, event: Option<Event>) {
     }
 }
 
+/// Client used to announce the torrent: the one of its category if it has its own, or the global one
+async fn client_for(torrent: &Torrent) -> Option<Client> {
+    if let Some(category) = &torrent.category
+        && let Some(client) = CATEGORY_CLIENTS.read().await.get(category)
+    {
+        return Some(client.clone());
+    }
+    CLIENT.read().await.clone()
+}
+
+/// Uploaded bytes to report since the last announce
+pub fn uploaded_since_last_announce(torrent: &Torrent, event: Option<Event>) -> u64 {
+    let elapsed: u64 = if event == Some(Event::Started) {
+        0
+    } else {
+        torrent.last_announce.elapsed().as_secs()
+    };
+    torrent.next_upload_speed as u64 * elapsed
+}
+
 // /// Check which torrents need to be announced and call the announce fuction when applicable
 // pub fn check_and_announce() {
 //     let list = TORRENTS.read().expect("Cannot get torrent list");
@@ -167,12 +369,14 @@ pub async fn announce(torrent: &mut Torrent, event: Option<Event>) {
 //     }
 // }
 
+/// Announce to an HTTP(S) tracker. On success, it returns the uploaded bytes that were reported.
 async fn announce_http(
     url: &str,
     torrent: &mut Torrent,
+    info_hash: &[u8; 20],
     client: &Client,
     event: Option<Event>,
-) -> u64 {
+) -> Result<u64, AnnounceError> {
     // announce parameters are built up in the query string, see:
     // https://www.bittorrent.org/beps/bep_0003.html trackers section
     // let mut query = vec![
@@ -232,7 +436,16 @@ async fn announce_http(
     let mut full_url = String::from(url);
     full_url.push(if full_url.contains('?') { '&' } else { '?' });
     full_url.push_str(&url_template);
-    let built_url = build_url(url, torrent, event, client.key.clone().to_string()).await;
+    let uploaded = uploaded_since_last_announce(torrent, event);
+    let built_url = build_url(
+        url,
+        torrent,
+        info_hash,
+        client,
+        event,
+        client.key.clone().to_string(),
+    )
+    .await;
     info!("Announce HTTP URL {built_url}");
 
     let mut request_builder = reqwest_client.get(&built_url);
@@ -241,114 +454,92 @@ async fn announce_http(
         request_builder = request_builder.header(&name, &value);
     }
 
-    match request_builder.send().await {
-        Ok(resp) => {
-            let status = resp.status().as_u16();
-            info!(
-                "\tTime since last announce: {}s \t interval: {}",
-                torrent.last_announce.elapsed().as_secs(),
-                torrent.interval
-            );
+    let mut resp = request_builder.send().await.map_err(|e| {
+        if e.is_timeout() {
+            AnnounceError::Timeout
+        } else {
+            AnnounceError::Network(e.to_string())
+        }
+    })?;
+    let status = resp.status().as_u16();
+    let limits = crate::torrent::bencode_limits();
+    if resp
+        .content_length()
+        .is_some_and(|length| length > limits.max_size as u64)
+    {
+        return Err(AnnounceError::InvalidResponse(format!(
+            "response is larger than {} bytes",
+            limits.max_size
+        )));
+    }
+    info!(
+        "\tTime since last announce: {}s \t interval: {}",
+        torrent.last_announce.elapsed().as_secs(),
+        torrent.interval
+    );
 
-            // read response body
-            let bytes = match resp.bytes().await {
-                Ok(b) => b,
-                Err(e) => {
-                    error!("Failed to read response bytes: {:?}", e);
-                    return torrent.interval; // return current interval
-                }
-            };
-            let bytes_vec = bytes.to_vec(); //convert Bytes to Vec<u8>
+    // read response body, chunked responses have no content length so the limit is checked while reading
+    let mut bytes_vec = Vec::new();
+    while let Some(chunk) = resp
+        .chunk()
+        .await
+        .map_err(|e| AnnounceError::Network(format!("Failed to read response bytes: {e}")))?
+    {
+        if bytes_vec.len() + chunk.len() > limits.max_size {
+            return Err(AnnounceError::InvalidResponse(format!(
+                "response is larger than {} bytes",
+                limits.max_size
+            )));
+        }
+        bytes_vec.extend_from_slice(&chunk);
+    }
 
-            // we start to check if the tracker has returned an error message, if yes, we will reannounce later
-            debug!(
-                "Tracker response: {:?}",
-                String::from_utf8_lossy(&bytes_vec)
-            );
+    // we start to check if the tracker has returned an error message, if yes, we will reannounce later
+    debug!(
+        "Tracker response: {:?}",
+        String::from_utf8_lossy(&bytes_vec)
+    );
 
-            // Bencode decoding
-            let mut decoder = BencodeDecoder::new(&bytes_vec);
-            match decoder.decode() {
-                Ok(bv) => {
-                    match bv {
-                        BencodeValue::Dictionary(dict) => {
-                            if let Some(BencodeValue::ByteString(msg)) =
-                                dict.get(b"failure reason".as_ref())
-                            {
-                                // If present, then no other keys may be present. The value is a human-readable error message as to why the request failed
-                                error!("Cannot announce: {:?}", std::str::from_utf8(msg));
-                                torrent.error_count += 1;
-                            } else {
-                                // Check for warning message (response still gets processed normally)
-                                if let Some(BencodeValue::ByteString(msg)) =
-                                    dict.get(b"warning message".as_ref())
-                                {
-                                    warn!("Announce with warning: {:?}", std::str::from_utf8(msg));
-                                }
-
-                                // Process response fields
-                                // Interval in seconds that the client should wait between sending regular requests to the tracker
-                                if let Some(BencodeValue::Integer(interval)) =
-                                    dict.get(b"interval".as_ref())
-                                {
-                                    torrent.interval = *interval as u64;
-                                }
-
-                                // (optional) Minimum announce interval. If present clients must not reannounce more frequently than this.
-                                if let Some(BencodeValue::Integer(mi)) =
-                                    dict.get(b"min interval".as_ref())
-                                {
-                                    torrent.min_interval = Some(*mi as u64);
-                                }
-
-                                // A string that the client should send back on its next announcements. If absent and
-                                // a previous announce sent a tracker id, do not discard the old value; keep using it.
-                                if let Some(BencodeValue::ByteString(tid)) =
-                                    dict.get(b"tracker_id".as_ref())
-                                {
-                                    match std::str::from_utf8(tid) {
-                                        Ok(tracker_id) => {
-                                            torrent.tracker_id = Some(tracker_id.to_string())
-                                        }
-                                        Err(e) => error!("Unable to decode tracker_id: {:?}", e),
-                                    }
-                                }
-
-                                // number of peers with the entire file, i.e. seeders (integer)
-                                if let Some(BencodeValue::Integer(value)) =
-                                    dict.get(b"complete".as_ref())
-                                {
-                                    torrent.seeders = *value as u16;
-                                }
-
-                                // number of leechers (integer)
-                                if let Some(BencodeValue::Integer(value)) =
-                                    dict.get(b"incomplete".as_ref())
-                                {
-                                    torrent.leechers = *value as u16;
-                                }
-
-                                // b"peers" not handled
-
-                                // Reset last_announce and error_count on successful response
-                                torrent.last_announce = std::time::Instant::now();
-                                torrent.error_count = 0;
-                            }
-                        }
-                        _ => error!("Response is not a dictionary"),
-                    }
-                }
-                Err(e) => error!("Bad response with HTTP status {status}: {:?}", e),
-            }
+    // Bencode decoding
+    let response: AnnounceResponse = from_bytes_with_limits(&bytes_vec, limits).map_err(|e| {
+        AnnounceError::InvalidResponse(format!("bad response with HTTP status {status}: {e}"))
+    })?;
+
+    if let Some(msg) = response.failure_reason {
+        return Err(AnnounceError::Tracker(
+            String::from_utf8_lossy(msg).into_owned(),
+        ));
+    }
+    if let Some(msg) = response.warning_message {
+        warn!("Announce with warning: {:?}", std::str::from_utf8(msg));
+    }
+    if let Some(interval) = response.interval {
+        torrent.interval = interval;
+    }
+    if let Some(min_interval) = response.min_interval {
+        torrent.min_interval = Some(min_interval);
+    }
+    // If absent and a previous announce sent a tracker id, do not discard the old value; keep using it.
+    if let Some(tid) = response.tracker_id {
+        match std::str::from_utf8(tid) {
+            Ok(tracker_id) => torrent.tracker_id = Some(tracker_id.to_string()),
+            Err(e) => error!("Unable to decode tracker_id: {:?}", e),
         }
-        Err(err) => error!("Cannot announce: {:?}", err),
     }
-    if let Some(min) = torrent.min_interval
-        && min > torrent.interval
-    {
-        return min;
+    if let Some(value) = response.complete {
+        torrent.seeders = value.min(u16::MAX as u64) as u16;
+    }
+    if let Some(value) = response.incomplete {
+        torrent.leechers = value.min(u16::MAX as u64) as u16;
     }
-    torrent.interval
+
+    // b"peers" not handled
+
+    // Reset last_announce and error_count on successful response
+    torrent.uploaded += uploaded;
+    torrent.last_announce = std::time::Instant::now();
+    torrent.error_count = 0;
+    Ok(uploaded)
 }
 
 /// Build the HTTP announce URLs for the listed trackers in the torrent file.
@@ -356,20 +547,16 @@ async fn announce_http(
 pub async fn build_url(
     url: &str,
     torrent: &mut Torrent,
+    info_hash: &[u8; 20],
+    client: &Client,
     event: Option<Event>,
     key: String,
 ) -> String {
     info!("Torrent {:?}: {}", event, torrent.name);
     //compute downloads and uploads
-    let elapsed: u64 = if event == Some(Event::Started) {
-        0
-    } else {
-        torrent.last_announce.elapsed().as_secs()
-    };
-    let uploaded: u64 = torrent.next_upload_speed as u64 * elapsed;
+    let uploaded = uploaded_since_last_announce(torrent, event);
 
     //build URL list
-    let client = (*CLIENT.read().await).clone().unwrap();
     let mut port = 55555u16;
     let mut numwant = 80u16;
     if let Some(config) = CONFIG.get() {
@@ -382,7 +569,7 @@ pub async fn build_url(
     result.push(if result.contains('?') { '&' } else { '?' });
     result.push_str(&client.query);
     let result = result
-        .replace("{infohash}", &torrent.info_hash_urlencoded)
+        .replace("{infohash}", &percent_encoding(info_hash))
         .replace("{key}", &key)
         .replace("{uploaded}", uploaded.to_string().as_str())
         .replace("{downloaded}", "0")
@@ -391,17 +578,7 @@ pub async fn build_url(
         .replace("{numwant}", &numwant.to_string())
         .replace("ipv6={ipv6}", "")
         .replace("{left}", "0")
-        .replace(
-            "{event}",
-            match event {
-                Some(e) => match e {
-                    Event::Started => "started",
-                    // Event::Completed => "completed",
-                    Event::Stopped => "stopped",
-                },
-                None => "",
-            },
-        );
+        .replace("{event}", event.map(|e| e.as_str()).unwrap_or(""));
     // info!(
     //     "\tUploaded: {}",
     //     byte_unit::Byte::from_u128(uploaded as u128)
@@ -441,4 +618,30 @@ mod tests {
         // Unsupported schemes
         assert!(!is_supported_url("wss://tracker.example.com/announce"));
     }
+
+    #[test]
+    fn test_announce_response() {
+        let data = b"d8:completei12e10:incompletei3e8:intervali1800e12:min intervali60e5:peers0:\
+            10:tracker id3:abce";
+        let response: AnnounceResponse = crate::bencode::from_bytes(data).unwrap();
+        assert_eq!(
+            response,
+            AnnounceResponse {
+                interval: Some(1800),
+                min_interval: Some(60),
+                tracker_id: Some(b"abc"),
+                complete: Some(12),
+                incomplete: Some(3),
+                ..Default::default()
+            }
+        );
+
+        let response: AnnounceResponse =
+            crate::bencode::from_bytes(b"d14:failure reason9:not founde").unwrap();
+        assert_eq!(response.failure_reason, Some(&b"not found"[..]));
+
+        let error =
+            crate::bencode::from_bytes::<AnnounceResponse>(b"d8:intervali-1ee").unwrap_err();
+        assert_eq!(error.path, "interval");
+    }
 }
@@ -5,13 +5,13 @@ use std::net::SocketAddr;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tokio::net::UdpSocket;
 use tokio::time::timeout;
-use tracing::{debug, error, info, warn};
+use tracing::{debug, info};
 use url::Url;
 
 use crate::CONFIG;
 use crate::torrent::Torrent;
 
-use super::tracker::Event;
+use super::tracker::{AnnounceError, Event, uploaded_since_last_announce};
 
 #[derive(Debug)]
 pub struct TrackerRequest {
@@ -35,6 +35,7 @@ pub struct TrackerResponse {
 }
 
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum TrackerError {
     IoError(std::io::Error),
     Timeout,
@@ -259,36 +260,38 @@ async fn resolve_tracker_addr(url: &str) -> Result<SocketAddr, TrackerError> {
         .ok_or_else(|| TrackerError::TrackerError(format!("Could not resolve hostname: {}", host)))
 }
 
-pub async fn announce_udp(url: &str, torrent: &mut Torrent, client: &Client, event: Option<Event>) {
+impl From<TrackerError> for AnnounceError {
+    fn from(err: TrackerError) -> Self {
+        match err {
+            TrackerError::IoError(e) => AnnounceError::Network(e.to_string()),
+            TrackerError::Timeout => AnnounceError::Timeout,
+            TrackerError::InvalidResponse => {
+                AnnounceError::InvalidResponse(String::from("invalid response from tracker"))
+            }
+            TrackerError::TrackerError(msg) => AnnounceError::Tracker(msg),
+            TrackerError::ParseError => AnnounceError::InvalidUrl,
+        }
+    }
+}
+
+/// Announce to an UDP tracker. On success, it returns the uploaded bytes that were reported.
+pub async fn announce_udp(
+    url: &str,
+    torrent: &mut Torrent,
+    info_hash: &[u8; 20],
+    client: &Client,
+    event: Option<Event>,
+) -> Result<u64, AnnounceError> {
     debug!("UDP announce to {}", url);
 
     // Resolve tracker address
-    let tracker_addr = match resolve_tracker_addr(url).await {
-        Ok(addr) => addr,
-        Err(e) => {
-            error!("Cannot resolve UDP tracker {}: {}", url, e);
-            torrent.error_count += 1;
-            return;
-        }
-    };
+    let tracker_addr = resolve_tracker_addr(url).await?;
 
     // Create tracker connection
-    let tracker = match UdpTracker::new(tracker_addr).await {
-        Ok(t) => t,
-        Err(e) => {
-            error!("Cannot connect to UDP tracker {}: {}", url, e);
-            torrent.error_count += 1;
-            return;
-        }
-    };
+    let tracker = UdpTracker::new(tracker_addr).await?;
 
     // Calculate uploaded bytes since last announce
-    let elapsed: u64 = if event == Some(Event::Started) {
-        0
-    } else {
-        torrent.last_announce.elapsed().as_secs()
-    };
-    let uploaded: u64 = torrent.next_upload_speed as u64 * elapsed;
+    let uploaded = uploaded_since_last_announce(torrent, event);
 
     // Convert peer_id to fixed-size array
     let peer_id_bytes = client.peer_id.as_bytes();
@@ -306,7 +309,7 @@ pub async fn announce_udp(url: &str, torrent: &mut Torrent, client: &Client, eve
     let numwant = config.numwant.unwrap_or(80) as i32;
 
     let request = TrackerRequest {
-        info_hash: torrent.info_hash,
+        info_hash: *info_hash,
         peer_id: peer_id_array,
         downloaded: 0,
         left: 0,
@@ -317,29 +320,23 @@ pub async fn announce_udp(url: &str, torrent: &mut Torrent, client: &Client, eve
         port,
     };
 
-    match tracker.announce(&request).await {
-        Ok(response) => {
-            // Update torrent state on successful announce
-            torrent.uploaded += uploaded;
-            torrent.interval = response.interval as u64;
-            torrent.seeders = response.seeders as u16;
-            torrent.leechers = response.leechers as u16;
-            torrent.last_announce = std::time::Instant::now();
-            torrent.error_count = 0;
-
-            info!(
-                "UDP announce OK: interval={}, seeders={}, leechers={}, peers={}",
-                response.interval,
-                response.seeders,
-                response.leechers,
-                response.peers.len()
-            );
-        }
-        Err(e) => {
-            warn!("UDP announce failed for {}: {}", url, e);
-            torrent.error_count += 1;
-        }
-    }
+    let response = tracker.announce(&request).await?;
+    // Update torrent state on successful announce
+    torrent.uploaded += uploaded;
+    torrent.interval = response.interval as u64;
+    torrent.seeders = response.seeders as u16;
+    torrent.leechers = response.leechers as u16;
+    torrent.last_announce = std::time::Instant::now();
+    torrent.error_count = 0;
+
+    info!(
+        "UDP announce OK: interval={}, seeders={}, leechers={}, peers={}",
+        response.interval,
+        response.seeders,
+        response.leechers,
+        response.peers.len()
+    );
+    Ok(uploaded)
 }
 
 #[cfg(test)]
@@ -1,8 +1,17 @@
 use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::Range;
 use std::str;
 
 use crate::torrent::TorrentError;
 
+pub mod cli;
+mod de;
+mod ser;
+
+pub use de::{from_bytes, from_bytes_with_limits, from_bytes_with_ref};
+pub use ser::{to_bytes, to_value};
+
 #[derive(Debug, PartialEq)]
 pub enum BencodeValue {
     Integer(i64),
@@ -11,26 +20,263 @@ pub enum BencodeValue {
     Dictionary(BTreeMap<Vec<u8>, BencodeValue>),
 }
 
-/// Errors that can occur during Bencode decoding.
-#[derive(Debug)]
-pub enum BencodeDecoderError {
+/// Kinds of errors that can occur during Bencode decoding.
+#[derive(Debug, PartialEq)]
+pub enum ErrorKind {
     InvalidFormat,
     UnexpectedEndOfInput,
     ParseIntError(String), // Store error message as String
     Utf8Error(String),     // Store error message as String
+    /// Lists and dictionaries are nested deeper than the limit
+    TooDeep(usize),
+    /// The input is larger than the limit
+    TooLarge(usize),
+    /// A byte string is longer than the limit
+    StringTooLong(usize),
+    /// Rejected in strict mode, the input is valid but not in its canonical form
+    NonCanonical(&'static str),
+    /// The value does not match the type it is deserialized to
+    Custom(String),
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ErrorKind::InvalidFormat => write!(f, "invalid format"),
+            ErrorKind::UnexpectedEndOfInput => write!(f, "unexpected end of input"),
+            ErrorKind::ParseIntError(e) => write!(f, "invalid number: {e}"),
+            ErrorKind::Utf8Error(e) => write!(f, "invalid UTF-8: {e}"),
+            ErrorKind::TooDeep(max) => write!(f, "more than {max} nested lists or dictionaries"),
+            ErrorKind::TooLarge(max) => write!(f, "input is larger than {max} bytes"),
+            ErrorKind::StringTooLong(max) => write!(f, "string is longer than {max} bytes"),
+            ErrorKind::NonCanonical(what) => write!(f, "not canonical: {what}"),
+            ErrorKind::Custom(message) => write!(f, "{message}"),
+        }
+    }
+}
+
+/// Error that occurred during Bencode decoding, with where it occurred.
+#[derive(Debug, PartialEq)]
+pub struct BencodeDecoderError {
+    pub kind: ErrorKind,
+    /// Position in the input, in bytes
+    pub offset: usize,
+    /// Dictionary keys and list indexes to the failing value, i.e. `info.files[3].length`, empty at the top level
+    pub path: String,
+}
+
+impl fmt::Display for BencodeDecoderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at byte {}", self.kind, self.offset)?;
+        if !self.path.is_empty() {
+            write!(f, " in {}", self.path)?;
+        }
+        Ok(())
+    }
+}
+
+/// Limits of the decoder, to not overflow the stack or allocate huge buffers with hostile input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// Maximum nesting of lists and dictionaries
+    pub max_depth: usize,
+    /// Maximum size of the input, in bytes
+    pub max_size: usize,
+    /// Maximum length of a byte string, in bytes
+    pub max_string_length: usize,
+    /// Reject input that is not canonical: unsorted or duplicate keys, leading zeros, `-0` and trailing data
+    pub strict: bool,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_depth: 64,
+            max_size: 32 * 1024 * 1024,
+            max_string_length: 16 * 1024 * 1024,
+            strict: false,
+        }
+    }
+}
+
+/// Step of the path to the value being decoded
+enum PathSegment {
+    /// Byte range of a dictionary key in the input
+    Key(Range<usize>),
+    /// Index in a list
+    Index(usize),
+}
+
+/// Format a path like `info.files[3].length`, keys are ranges of `data`
+fn format_path(data: &[u8], segments: &[PathSegment]) -> String {
+    let mut path = String::new();
+    for segment in segments {
+        match segment {
+            PathSegment::Key(range) => {
+                if !path.is_empty() {
+                    path.push('.');
+                }
+                path.push_str(&String::from_utf8_lossy(&data[range.clone()]));
+            }
+            PathSegment::Index(index) => path.push_str(&format!("[{index}]")),
+        }
+    }
+    path
+}
+
+/// Borrowed view of a decoded value: byte strings are slices of the input, so large strings like `pieces` are never
+/// copied. Lists and dictionaries are read lazily from their raw bytes, that are validated by the decoder.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BencodeRef<'a> {
+    Integer(i64),
+    ByteString(&'a [u8]),
+    List(ListRef<'a>),
+    Dictionary(DictRef<'a>),
+}
+
+/// Raw bytes of a decoded list (`l...e`)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ListRef<'a> {
+    raw: &'a [u8],
+}
+
+/// Raw bytes of a decoded dictionary (`d...e`)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DictRef<'a> {
+    raw: &'a [u8],
+}
+
+/// Read the value at `position` of data validated by the decoder and move the position after it
+fn read_value<'a>(data: &'a [u8], position: &mut usize) -> Option<BencodeRef<'a>> {
+    let start = *position;
+    match data.get(start)? {
+        b'i' => {
+            let end = start + data[start..].iter().position(|b| *b == b'e')?;
+            *position = end + 1;
+            let number = str::from_utf8(&data[start + 1..end]).ok()?;
+            number.parse().ok().map(BencodeRef::Integer)
+        }
+        b'0'..=b'9' => {
+            let colon = start + data[start..].iter().position(|b| *b == b':')?;
+            let len: usize = str::from_utf8(&data[start..colon]).ok()?.parse().ok()?;
+            let end = (colon + 1).checked_add(len)?;
+            let string = data.get(colon + 1..end)?;
+            *position = end;
+            Some(BencodeRef::ByteString(string))
+        }
+        kind @ (b'l' | b'd') => {
+            let mut end = start + 1;
+            while *data.get(end)? != b'e' {
+                read_value(data, &mut end)?;
+            }
+            end += 1;
+            *position = end;
+            let raw = &data[start..end];
+            Some(if *kind == b'l' {
+                BencodeRef::List(ListRef { raw })
+            } else {
+                BencodeRef::Dictionary(DictRef { raw })
+            })
+        }
+        _ => None,
+    }
 }
 
-// Convert ParseIntError to BencodeDecoderError
-impl From<std::num::ParseIntError> for BencodeDecoderError {
-    fn from(err: std::num::ParseIntError) -> Self {
-        BencodeDecoderError::ParseIntError(err.to_string())
+impl<'a> BencodeRef<'a> {
+    pub fn as_integer(&self) -> Option<i64> {
+        match self {
+            BencodeRef::Integer(i) => Some(*i),
+            _ => None,
+        }
+    }
+
+    pub fn as_bytes(&self) -> Option<&'a [u8]> {
+        match self {
+            BencodeRef::ByteString(b) => Some(b),
+            _ => None,
+        }
+    }
+
+    pub fn as_list(&self) -> Option<ListRef<'a>> {
+        match self {
+            BencodeRef::List(list) => Some(*list),
+            _ => None,
+        }
+    }
+
+    pub fn as_dict(&self) -> Option<DictRef<'a>> {
+        match self {
+            BencodeRef::Dictionary(dict) => Some(*dict),
+            _ => None,
+        }
+    }
+
+    /// Copy the value, i.e. to encode it
+    pub fn to_value(&self) -> BencodeValue {
+        match self {
+            BencodeRef::Integer(i) => BencodeValue::Integer(*i),
+            BencodeRef::ByteString(b) => BencodeValue::ByteString(b.to_vec()),
+            BencodeRef::List(list) => {
+                BencodeValue::List(list.iter().map(|v| v.to_value()).collect())
+            }
+            BencodeRef::Dictionary(dict) => BencodeValue::Dictionary(
+                dict.iter()
+                    .map(|(key, value)| (key.to_vec(), value.to_value()))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+impl<'a> ListRef<'a> {
+    pub fn iter(&self) -> impl Iterator<Item = BencodeRef<'a>> + use<'a> {
+        let raw = self.raw;
+        let mut position = 1; // after 'l'
+        std::iter::from_fn(move || {
+            if raw.get(position) == Some(&b'e') {
+                return None;
+            }
+            read_value(raw, &mut position)
+        })
     }
 }
 
-// Convert Utf8Error to BencodeDecoderError
-impl From<std::str::Utf8Error> for BencodeDecoderError {
-    fn from(err: std::str::Utf8Error) -> Self {
-        BencodeDecoderError::Utf8Error(err.to_string())
+impl<'a> DictRef<'a> {
+    /// Keys and values, in the order of the input
+    pub fn iter(&self) -> impl Iterator<Item = (&'a [u8], BencodeRef<'a>)> + use<'a> {
+        let raw = self.raw;
+        let mut position = 1; // after 'd'
+        std::iter::from_fn(move || {
+            if raw.get(position) == Some(&b'e') {
+                return None;
+            }
+            let key = read_value(raw, &mut position)?.as_bytes()?;
+            Some((key, read_value(raw, &mut position)?))
+        })
+    }
+
+    /// Original bytes of the value of a key. For duplicate keys, it is the last value like in [`BencodeValue`].
+    ///
+    /// It is used to hash the `info` dictionary as it is in the file: re-encoding a non canonical dictionary (unsorted
+    /// or duplicate keys, integers with leading zeros) would give another info hash.
+    pub fn get_raw(&self, key: &[u8]) -> Option<&'a [u8]> {
+        let raw = self.raw;
+        let mut position = 1; // after 'd'
+        let mut found = None;
+        while *raw.get(position)? != b'e' {
+            let current = read_value(raw, &mut position)?.as_bytes()?;
+            let start = position;
+            read_value(raw, &mut position)?;
+            if current == key {
+                found = Some(&raw[start..position]);
+            }
+        }
+        found
+    }
+
+    /// Value of a key, the dictionary is searched on each call
+    pub fn get(&self, key: &[u8]) -> Option<BencodeRef<'a>> {
+        self.get_raw(key).and_then(|raw| read_value(raw, &mut 0))
     }
 }
 
@@ -38,25 +284,111 @@ impl From<std::str::Utf8Error> for BencodeDecoderError {
 pub struct BencodeDecoder<'a> {
     data: &'a [u8],
     position: usize,
+    limits: Limits,
+    /// Number of lists and dictionaries being decoded
+    depth: usize,
+    /// Path to the value being decoded, for errors
+    path: Vec<PathSegment>,
 }
 
 impl<'a> BencodeDecoder<'a> {
     /// Creates a new decoder with the provided Bencode data.
     pub fn new(data: &'a [u8]) -> Self {
-        BencodeDecoder { data, position: 0 }
+        Self::with_limits(data, Limits::default())
+    }
+
+    /// Creates a new decoder with the provided Bencode data and limits.
+    pub fn with_limits(data: &'a [u8], limits: Limits) -> Self {
+        BencodeDecoder {
+            data,
+            position: 0,
+            limits,
+            depth: 0,
+            path: Vec::new(),
+        }
     }
 
     /// Decodes the next Bencode value from the input.
     pub fn decode(&mut self) -> Result<BencodeValue, BencodeDecoderError> {
-        self.peek_byte()
-            .ok_or(BencodeDecoderError::UnexpectedEndOfInput)
-            .and_then(|byte| match byte {
-                b'i' => self.decode_integer(),
-                b'0'..=b'9' => self.decode_byte_string(),
-                b'l' => self.decode_list(),
-                b'd' => self.decode_dictionary(),
-                _ => Err(BencodeDecoderError::InvalidFormat),
-            })
+        self.decode_ref().map(|value| value.to_value())
+    }
+
+    /// Decodes the next Bencode value from the input without copying it. The whole value is validated.
+    pub fn decode_ref(&mut self) -> Result<BencodeRef<'a>, BencodeDecoderError> {
+        if self.data.len() > self.limits.max_size {
+            return Err(self.error(ErrorKind::TooLarge(self.limits.max_size), 0));
+        }
+        self.depth = 0;
+        self.path.clear();
+        let value = self.decode_value()?;
+        if self.limits.strict && self.position != self.data.len() {
+            return Err(self.error(ErrorKind::NonCanonical("trailing data"), self.position));
+        }
+        Ok(value)
+    }
+
+    /// Build an error at the given offset, with the path to the value being decoded
+    fn error(&self, kind: ErrorKind, offset: usize) -> BencodeDecoderError {
+        BencodeDecoderError {
+            kind,
+            offset,
+            path: format_path(self.data, &self.path),
+        }
+    }
+
+    /// Decodes the value at the current position.
+    fn decode_value(&mut self) -> Result<BencodeRef<'a>, BencodeDecoderError> {
+        match self.peek_byte() {
+            Some(b'i') => self.decode_integer(),
+            Some(b'0'..=b'9') => {
+                let range = self.read_byte_string()?;
+                Ok(BencodeRef::ByteString(&self.data[range]))
+            }
+            Some(b'l') => self.decode_list(),
+            Some(b'd') => self.decode_dictionary(),
+            Some(_) => Err(self.error(ErrorKind::InvalidFormat, self.position)),
+            None => Err(self.error(ErrorKind::UnexpectedEndOfInput, self.position)),
+        }
+    }
+
+    /// Enter a list or a dictionary
+    fn enter(&mut self) -> Result<(), BencodeDecoderError> {
+        if self.depth >= self.limits.max_depth {
+            return Err(self.error(ErrorKind::TooDeep(self.limits.max_depth), self.position));
+        }
+        self.depth += 1;
+        self.consume_byte(); // Consume 'l' or 'd'
+        Ok(())
+    }
+
+    /// Peeks at the end of a list or a dictionary, the input must not end before it
+    fn at_end(&self) -> Result<bool, BencodeDecoderError> {
+        match self.peek_byte() {
+            Some(byte) => Ok(byte == b'e'),
+            None => Err(self.error(ErrorKind::UnexpectedEndOfInput, self.position)),
+        }
+    }
+
+    /// Parse the number of an integer or the length of a byte string that starts at `offset`. In strict mode, it
+    /// must not have a sign `+`, leading zeros nor be `-0`.
+    fn parse_number<T: str::FromStr<Err = std::num::ParseIntError>>(
+        &self,
+        slice: &[u8],
+        offset: usize,
+    ) -> Result<T, BencodeDecoderError> {
+        let number = str::from_utf8(slice)
+            .map_err(|e| self.error(ErrorKind::Utf8Error(e.to_string()), offset))?;
+        let digits = number.strip_prefix('-').unwrap_or(number);
+        if self.limits.strict
+            && (digits.starts_with('+')
+                || (digits.len() > 1 && digits.starts_with('0'))
+                || number == "-0")
+        {
+            return Err(self.error(ErrorKind::NonCanonical("number"), offset));
+        }
+        number.parse().map_err(|e: std::num::ParseIntError| {
+            self.error(ErrorKind::ParseIntError(e.to_string()), offset)
+        })
     }
 
     /// Peeks at the current byte without advancing the position.
@@ -87,74 +419,90 @@ impl<'a> BencodeDecoder<'a> {
             }
             self.position += 1;
         }
-        Err(BencodeDecoderError::UnexpectedEndOfInput)
+        Err(self.error(ErrorKind::UnexpectedEndOfInput, self.position))
     }
 
     /// Decodes an integer value (i<integer>e).
-    fn decode_integer(&mut self) -> Result<BencodeValue, BencodeDecoderError> {
+    fn decode_integer(&mut self) -> Result<BencodeRef<'a>, BencodeDecoderError> {
         self.consume_byte(); // Consume 'i'
+        let start = self.position;
         let num_slice = self.read_until(b'e')?;
-        let num_str = str::from_utf8(num_slice)?;
-        let num: i64 = num_str.parse()?;
-        Ok(BencodeValue::Integer(num))
+        Ok(BencodeRef::Integer(self.parse_number(num_slice, start)?))
     }
 
-    /// Decodes a byte string (<length>:<string>).
-    fn decode_byte_string(&mut self) -> Result<BencodeValue, BencodeDecoderError> {
+    /// Decodes a byte string (<length>:<string>) and returns the range of the string in the input.
+    fn read_byte_string(&mut self) -> Result<Range<usize>, BencodeDecoderError> {
+        let start = self.position;
         let len_slice = self.read_until(b':')?;
-        let len_str = str::from_utf8(len_slice)?;
-        let len: usize = len_str.parse()?;
+        let len: usize = self.parse_number(len_slice, start)?;
 
-        if self.position + len > self.data.len() {
-            return Err(BencodeDecoderError::UnexpectedEndOfInput);
+        if len > self.limits.max_string_length {
+            return Err(self.error(
+                ErrorKind::StringTooLong(self.limits.max_string_length),
+                start,
+            ));
+        }
+        if len > self.data.len() - self.position {
+            return Err(self.error(ErrorKind::UnexpectedEndOfInput, self.data.len()));
         }
 
         let start = self.position;
         self.position += len;
-        Ok(BencodeValue::ByteString(
-            self.data[start..self.position].to_vec(),
-        ))
+        Ok(start..self.position)
     }
 
     /// Decodes a list (l<element1><element2>...e).
-    fn decode_list(&mut self) -> Result<BencodeValue, BencodeDecoderError> {
-        self.consume_byte(); // Consume 'l'
-        let mut list = Vec::new();
-        while self
-            .peek_byte()
-            .ok_or(BencodeDecoderError::UnexpectedEndOfInput)?
-            != b'e'
-        {
-            list.push(self.decode()?); // Recursive call
+    fn decode_list(&mut self) -> Result<BencodeRef<'a>, BencodeDecoderError> {
+        let start = self.position;
+        self.enter()?;
+        let mut index = 0;
+        while !self.at_end()? {
+            self.path.push(PathSegment::Index(index));
+            self.decode_value()?; // Recursive call
+            self.path.pop();
+            index += 1;
         }
         self.consume_byte(); // Consume 'e'
-        Ok(BencodeValue::List(list))
+        self.depth -= 1;
+        Ok(BencodeRef::List(ListRef {
+            raw: &self.data[start..self.position],
+        }))
     }
 
     /// Decodes a dictionary (d<key1><value1><key2><value2>...e).
-    fn decode_dictionary(&mut self) -> Result<BencodeValue, BencodeDecoderError> {
-        self.consume_byte(); // Consume 'd'
-        let mut dict = BTreeMap::new();
-        while self
-            .peek_byte()
-            .ok_or(BencodeDecoderError::UnexpectedEndOfInput)?
-            != b'e'
-        {
-            let key = match self.decode_byte_string()? {
-                // Keys must be ByteStrings
-                BencodeValue::ByteString(b) => b,
-                _ => return Err(BencodeDecoderError::InvalidFormat), // Key is not a string
-            };
-            let value = self.decode()?; // Recursive call
-            dict.insert(key, value);
+    fn decode_dictionary(&mut self) -> Result<BencodeRef<'a>, BencodeDecoderError> {
+        let start = self.position;
+        self.enter()?;
+        let mut last_key: Option<&[u8]> = None;
+        while !self.at_end()? {
+            // Keys must be ByteStrings
+            let key_start = self.position;
+            if !self.peek_byte().is_some_and(|b| b.is_ascii_digit()) {
+                return Err(self.error(ErrorKind::InvalidFormat, key_start));
+            }
+            let key_range = self.read_byte_string()?;
+            let key = &self.data[key_range.clone()];
+            // keys must be sorted, so each key is greater than the previous one
+            if self.limits.strict && last_key.is_some_and(|last| last >= key) {
+                return Err(self.error(
+                    ErrorKind::NonCanonical("unsorted or duplicate key"),
+                    key_start,
+                ));
+            }
+            last_key = Some(key);
+            self.path.push(PathSegment::Key(key_range));
+            self.decode_value()?; // Recursive call
+            self.path.pop();
         }
         self.consume_byte(); // Consume 'e'
-        Ok(BencodeValue::Dictionary(dict))
+        self.depth -= 1;
+        Ok(BencodeRef::Dictionary(DictRef {
+            raw: &self.data[start..self.position],
+        }))
     }
 }
 
-// This is needed because our simple decoder doesn't give us the raw byte range of the 'info' dict.
-// A more advanced parser might store byte ranges during parsing.
+/// Encode a value in its canonical form (sorted dictionary keys)
 pub fn encode_bencode_value(
     value: &BencodeValue,
     buffer: &mut Vec<u8>,
@@ -352,7 +700,10 @@ mod tests {
         let mut decoder = BencodeDecoder::new(b"i42");
         assert!(matches!(
             decoder.decode(),
-            Err(BencodeDecoderError::UnexpectedEndOfInput)
+            Err(BencodeDecoderError {
+                kind: ErrorKind::UnexpectedEndOfInput,
+                ..
+            })
         ));
     }
 
@@ -361,7 +712,10 @@ mod tests {
         let mut decoder = BencodeDecoder::new(b"x");
         assert!(matches!(
             decoder.decode(),
-            Err(BencodeDecoderError::InvalidFormat)
+            Err(BencodeDecoderError {
+                kind: ErrorKind::InvalidFormat,
+                ..
+            })
         ));
     }
 
@@ -370,8 +724,167 @@ mod tests {
         let mut decoder = BencodeDecoder::new(b"10:short");
         assert!(matches!(
             decoder.decode(),
-            Err(BencodeDecoderError::UnexpectedEndOfInput)
+            Err(BencodeDecoderError {
+                kind: ErrorKind::UnexpectedEndOfInput,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_raw_value() {
+        // keys are not sorted, the integer has a leading zero and "b" is duplicated
+        let data = b"d4:infod1:bi1e1:ai02ee1:b1:x1:b2:yye";
+        let root = BencodeDecoder::new(data)
+            .decode_ref()
+            .unwrap()
+            .as_dict()
+            .unwrap();
+        assert_eq!(root.get_raw(b"info"), Some(b"d1:bi1e1:ai02ee".as_ref()));
+        assert_eq!(root.get_raw(b"b"), Some(b"2:yy".as_ref()));
+        // nested keys are not at the top level
+        assert_eq!(root.get_raw(b"a"), None);
+        assert_eq!(root.get_raw(b"missing"), None);
+    }
+
+    #[test]
+    fn test_decode_ref() {
+        let data = b"d4:infod6:lengthi42e6:pieces3:abce4:listl1:ai-1eee";
+        let root = BencodeDecoder::new(data)
+            .decode_ref()
+            .unwrap()
+            .as_dict()
+            .unwrap();
+        let info = root.get(b"info").unwrap().as_dict().unwrap();
+        assert_eq!(info.get(b"length").unwrap().as_integer(), Some(42));
+        assert_eq!(
+            info.get(b"pieces").unwrap().as_bytes(),
+            Some(b"abc".as_ref())
+        );
+        let list = root.get(b"list").unwrap().as_list().unwrap();
+        assert_eq!(
+            list.iter().collect::<Vec<_>>(),
+            vec![BencodeRef::ByteString(b"a"), BencodeRef::Integer(-1)]
+        );
+        assert_eq!(
+            root.iter().map(|(key, _)| key).collect::<Vec<_>>(),
+            vec![b"info".as_ref(), b"list"]
+        );
+        // the owned value is the same
+        assert_eq!(
+            BencodeDecoder::new(data).decode().unwrap(),
+            BencodeRef::Dictionary(root).to_value()
+        );
+    }
+
+    #[test]
+    fn test_error_location() {
+        let data = b"d4:infod5:filesld6:lengthi1eed6:lengthi1xeeeee";
+        let error = BencodeDecoder::new(data).decode().unwrap_err();
+        assert!(matches!(error.kind, ErrorKind::ParseIntError(_)));
+        assert_eq!(error.offset, 39);
+        assert_eq!(error.path, "info.files[1].length");
+        assert!(
+            error
+                .to_string()
+                .ends_with("at byte 39 in info.files[1].length")
+        );
+
+        let error = BencodeDecoder::new(b"l1:ai2e").decode().unwrap_err();
+        assert_eq!(error.kind, ErrorKind::UnexpectedEndOfInput);
+        assert_eq!(error.offset, 7);
+        assert_eq!(error.path, "");
+        assert_eq!(error.to_string(), "unexpected end of input at byte 7");
+
+        // a key that is not a string
+        let error = BencodeDecoder::new(b"d1:ad1:bi1ei2ei3eee")
+            .decode()
+            .unwrap_err();
+        assert_eq!(error.kind, ErrorKind::InvalidFormat);
+        assert_eq!(error.offset, 11);
+        assert_eq!(error.path, "a");
+    }
+
+    #[test]
+    fn test_limits() {
+        let limits = Limits {
+            max_depth: 2,
+            max_size: 16,
+            max_string_length: 4,
+            strict: false,
+        };
+        assert!(
+            BencodeDecoder::with_limits(b"lli1eee", limits)
+                .decode()
+                .is_ok()
+        );
+        assert!(matches!(
+            BencodeDecoder::with_limits(b"llli1eeee", limits).decode(),
+            Err(BencodeDecoderError {
+                kind: ErrorKind::TooDeep(2),
+                ..
+            })
+        ));
+        assert!(matches!(
+            BencodeDecoder::with_limits(b"d3:bard3:fooleee", limits).decode(),
+            Err(BencodeDecoderError {
+                kind: ErrorKind::TooDeep(2),
+                ..
+            })
+        ));
+        assert!(matches!(
+            BencodeDecoder::with_limits(b"5:hello", limits).decode(),
+            Err(BencodeDecoderError {
+                kind: ErrorKind::StringTooLong(4),
+                ..
+            })
+        ));
+        assert!(matches!(
+            BencodeDecoder::with_limits(b"l4:spam4:spam4:spame", limits).decode(),
+            Err(BencodeDecoderError {
+                kind: ErrorKind::TooLarge(16),
+                ..
+            })
         ));
+        // a huge length must not overflow
+        assert!(matches!(
+            BencodeDecoder::new(b"18446744073709551615:a").decode(),
+            Err(BencodeDecoderError {
+                kind: ErrorKind::StringTooLong(_),
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_strict() {
+        let strict = Limits {
+            strict: true,
+            ..Limits::default()
+        };
+        for data in [
+            b"d1:bi1e1:ai2ee".as_ref(),
+            b"d1:ai1e1:ai2ee",
+            b"i01e",
+            b"i-0e",
+            b"i+1e",
+            b"01:a",
+            b"i1ei2e",
+        ] {
+            assert!(BencodeDecoder::new(data).decode().is_ok());
+            assert!(matches!(
+                BencodeDecoder::with_limits(data, strict).decode(),
+                Err(BencodeDecoderError {
+                    kind: ErrorKind::NonCanonical(_),
+                    ..
+                })
+            ));
+        }
+        assert!(
+            BencodeDecoder::with_limits(b"d1:ai-1e1:bli0ei10eee", strict)
+                .decode()
+                .is_ok()
+        );
     }
 
     // --- Encoder Tests ---
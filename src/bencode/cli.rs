@@ -0,0 +1,246 @@
+// `bencode` command: decode bencoded data to JSON or to an indented tree to debug torrent files and tracker responses,
+// and encode JSON back to bencode with the serde serializer.
+//
+// Byte strings that are valid UTF-8 are JSON strings, the other ones are written in hexadecimal with a `hex:` prefix.
+// UTF-8 strings that start with `hex:` are written in hexadecimal too, so decoding then encoding gives the same bytes
+// (with sorted dictionary keys).
+use std::fmt::Write as _;
+use std::io::{Read, Write as _};
+use std::path::Path;
+
+use serde::ser::{Error as _, SerializeMap};
+use serde::{Serialize, Serializer};
+use serde_json::{Map, Value};
+
+use super::{BencodeDecoder, BencodeRef, BencodeValue, to_bytes, to_value};
+use crate::utils::{from_hex, to_hex};
+
+const HEX_PREFIX: &str = "hex:";
+/// Bytes of binary strings shown in the tree, i.e. `pieces` can be megabytes long
+const TREE_MAX_BYTES: usize = 32;
+
+/// Output of the `bencode` command
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    /// Decode to pretty JSON
+    Json,
+    /// Decode to an indented tree
+    Tree,
+    /// Encode JSON to bencode
+    Encode,
+}
+
+/// Text of a byte string: UTF-8 or `hex:` followed by the bytes in hexadecimal
+fn text(bytes: &[u8]) -> String {
+    match std::str::from_utf8(bytes) {
+        Ok(text) if !text.starts_with(HEX_PREFIX) => text.to_string(),
+        _ => format!("{HEX_PREFIX}{}", to_hex(bytes)),
+    }
+}
+
+/// Bytes of a JSON string written by [`text`]
+fn bytes(text: &str) -> Result<Vec<u8>, String> {
+    match text.strip_prefix(HEX_PREFIX) {
+        Some(hex) => from_hex(hex).ok_or_else(|| format!("invalid hexadecimal string: {text}")),
+        None => Ok(text.as_bytes().to_vec()),
+    }
+}
+
+/// Convert a bencode value to JSON, dictionary keys are sorted
+pub fn to_json(value: BencodeRef) -> Value {
+    match value {
+        BencodeRef::Integer(i) => Value::Number(i.into()),
+        BencodeRef::ByteString(b) => Value::String(text(b)),
+        BencodeRef::List(list) => Value::Array(list.iter().map(to_json).collect()),
+        BencodeRef::Dictionary(dict) => Value::Object(
+            dict.iter()
+                .map(|(key, value)| (text(key), to_json(value)))
+                .collect::<Map<_, _>>(),
+        ),
+    }
+}
+
+/// JSON written by [`to_json`], for the bencode serializer: strings are byte strings and `null` is not supported
+struct Json<'a>(&'a Value);
+
+/// String of the JSON, the bytes of [`bytes`]
+struct Text<'a>(&'a str);
+
+impl Serialize for Json<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.0 {
+            Value::Null => Err(S::Error::custom("null is not supported")),
+            Value::String(s) => Text(s).serialize(serializer),
+            Value::Array(items) => serializer.collect_seq(items.iter().map(Json)),
+            Value::Object(entries) => {
+                let mut map = serializer.serialize_map(Some(entries.len()))?;
+                for (key, value) in entries {
+                    map.serialize_entry(&Text(key), &Json(value))?;
+                }
+                map.end()
+            }
+            // numbers must be integers, booleans are 0 or 1
+            other => other.serialize(serializer),
+        }
+    }
+}
+
+impl Serialize for Text<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(&bytes(self.0).map_err(S::Error::custom)?)
+    }
+}
+
+/// Convert JSON to a bencode value: numbers must be integers, booleans are 0 or 1 and `null` is not supported
+pub fn from_json(value: &Value) -> Result<BencodeValue, String> {
+    to_value(&Json(value)).map_err(|e| e.to_string())
+}
+
+/// Indented tree of a bencode value, one line per value
+pub fn to_tree(value: BencodeRef) -> String {
+    let mut tree = String::new();
+    write_tree(&mut tree, value, 0);
+    tree
+}
+
+fn write_tree(tree: &mut String, value: BencodeRef, depth: usize) {
+    match value {
+        BencodeRef::Integer(i) => {
+            let _ = writeln!(tree, "{i}");
+        }
+        BencodeRef::ByteString(b) => match std::str::from_utf8(b) {
+            Ok(text) => {
+                let _ = writeln!(tree, "{text:?}");
+            }
+            Err(_) if b.len() > TREE_MAX_BYTES => {
+                let _ = writeln!(
+                    tree,
+                    "{HEX_PREFIX}{}… ({} bytes)",
+                    to_hex(&b[..TREE_MAX_BYTES]),
+                    b.len()
+                );
+            }
+            Err(_) => {
+                let _ = writeln!(tree, "{HEX_PREFIX}{} ({} bytes)", to_hex(b), b.len());
+            }
+        },
+        BencodeRef::List(list) => {
+            let _ = writeln!(tree, "list ({})", list.iter().count());
+            for (i, item) in list.iter().enumerate() {
+                let _ = write!(tree, "{:indent$}[{i}] ", "", indent = (depth + 1) * 2);
+                write_tree(tree, item, depth + 1);
+            }
+        }
+        BencodeRef::Dictionary(dict) => {
+            let _ = writeln!(tree, "dict ({})", dict.iter().count());
+            for (key, value) in dict.iter() {
+                let _ = write!(
+                    tree,
+                    "{:indent$}{}: ",
+                    "",
+                    text(key),
+                    indent = (depth + 1) * 2
+                );
+                write_tree(tree, value, depth + 1);
+            }
+        }
+    }
+}
+
+/// Read a file, or stdin without a path
+fn read_input(path: Option<&Path>) -> std::io::Result<Vec<u8>> {
+    match path {
+        Some(path) => std::fs::read(path),
+        None => {
+            let mut data = Vec::new();
+            std::io::stdin().read_to_end(&mut data)?;
+            Ok(data)
+        }
+    }
+}
+
+/// Convert the input to the output of the mode
+fn convert(data: &[u8], mode: Mode) -> Result<Vec<u8>, String> {
+    if mode == Mode::Encode {
+        let json: Value = serde_json::from_slice(data).map_err(|e| e.to_string())?;
+        return to_bytes(&Json(&json)).map_err(|e| e.to_string());
+    }
+    let value = BencodeDecoder::new(data)
+        .decode_ref()
+        .map_err(|e| e.to_string())?;
+    Ok(match mode {
+        Mode::Tree => to_tree(value).into_bytes(),
+        _ => {
+            let mut json = serde_json::to_string_pretty(&to_json(value)).unwrap_or_default();
+            json.push('\n');
+            json.into_bytes()
+        }
+    })
+}
+
+/// `bencode` command: convert a file, or stdin without a path, to stdout. Returns false on failure.
+pub fn run(path: Option<&Path>, mode: Mode) -> bool {
+    let name = path.map_or(String::from("stdin"), |p| p.display().to_string());
+    let result = read_input(path)
+        .map_err(|e| e.to_string())
+        .and_then(|data| convert(&data, mode))
+        .and_then(|output| {
+            std::io::stdout()
+                .write_all(&output)
+                .map_err(|e| e.to_string())
+        });
+    match result {
+        Ok(()) => true,
+        Err(e) => {
+            eprintln!("{name}: {e}");
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json() {
+        let data = b"d4:infod6:lengthi-42e4:name4:hex:6:pieces2:\xff\x00e4:listl1:aee";
+        let json = to_json(BencodeDecoder::new(data).decode_ref().unwrap());
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "info": {"length": -42, "pieces": "hex:ff00", "name": "hex:6865783a"},
+                "list": ["a"]
+            })
+        );
+        assert_eq!(
+            convert(json.to_string().as_bytes(), Mode::Encode).unwrap(),
+            data
+        );
+
+        assert!(from_json(&serde_json::json!([1.5])).is_err());
+        assert!(from_json(&serde_json::json!(u64::MAX)).is_err());
+        assert!(from_json(&serde_json::json!({"a": null})).is_err());
+        assert!(from_json(&serde_json::json!("hex:zz")).is_err());
+        assert_eq!(
+            from_json(&serde_json::json!(true)),
+            Ok(BencodeValue::Integer(1))
+        );
+    }
+
+    #[test]
+    fn test_tree() {
+        let data = b"d8:announce3:url4:infod6:pieces40:\
+            \xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\
+            \xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\
+            e4:listli1eleee";
+        let tree = to_tree(BencodeDecoder::new(data).decode_ref().unwrap());
+        let expected = format!(
+            "dict (3)\n  announce: \"url\"\n  info: dict (1)\n    pieces: hex:{}… (40 bytes)\n  list: list (2)\n    \
+            [0] 1\n    [1] list (0)\n",
+            "ff".repeat(32)
+        );
+        assert_eq!(tree, expected);
+        assert!(convert(b"i1", Mode::Tree).is_err());
+    }
+}
@@ -0,0 +1,373 @@
+// Serde deserializer, to read bencoded data into typed structs:
+//
+//     #[derive(Deserialize)]
+//     struct Response<'a> {
+//         interval: u64,
+//         #[serde(rename = "failure reason", borrow)]
+//         failure_reason: Option<&'a [u8]>,
+//     }
+//
+// The input is validated by `BencodeDecoder` first, then values are read from the borrowed input: strings and bytes
+// can be borrowed (`&'a str`, `&'a [u8]`). Integers are `i64` and are converted to the other integer types when they
+// fit, `bool` is an integer 0 or 1 and enums are unit variants written as strings.
+use std::fmt;
+
+use serde::Deserialize;
+use serde::de::{self, DeserializeSeed, IntoDeserializer, MapAccess, SeqAccess, Visitor};
+
+use super::{
+    BencodeDecoder, BencodeDecoderError, BencodeRef, ErrorKind, Limits, PathSegment, format_path,
+    read_value,
+};
+
+/// Deserialize a type from bencoded data, with the default limits
+pub fn from_bytes<'de, T: Deserialize<'de>>(data: &'de [u8]) -> Result<T, BencodeDecoderError> {
+    from_bytes_with_limits(data, Limits::default())
+}
+
+/// Deserialize a type from bencoded data. Errors have the offset and the path of the value that does not match.
+pub fn from_bytes_with_limits<'de, T: Deserialize<'de>>(
+    data: &'de [u8],
+    limits: Limits,
+) -> Result<T, BencodeDecoderError> {
+    BencodeDecoder::with_limits(data, limits).decode_ref()?;
+    deserialize(data)
+}
+
+/// Deserialize a type from bencoded data and also return the decoded value, i.e. for the raw bytes of a dictionary.
+/// The data is only decoded once.
+pub fn from_bytes_with_ref<'de, T: Deserialize<'de>>(
+    data: &'de [u8],
+    limits: Limits,
+) -> Result<(T, BencodeRef<'de>), BencodeDecoderError> {
+    let value = BencodeDecoder::with_limits(data, limits).decode_ref()?;
+    Ok((deserialize(data)?, value))
+}
+
+/// Deserialize a type from data validated by `BencodeDecoder`
+fn deserialize<'de, T: Deserialize<'de>>(data: &'de [u8]) -> Result<T, BencodeDecoderError> {
+    let mut path = Vec::new();
+    let deserializer = Deserializer {
+        data,
+        position: 0,
+        path: &mut path,
+    };
+    T::deserialize(deserializer).map_err(|e| match e {
+        Error::Located(e) => e,
+        Error::Custom(message) => BencodeDecoderError {
+            kind: ErrorKind::Custom(message),
+            offset: 0,
+            path: String::new(),
+        },
+    })
+}
+
+/// Deserialization error, it is located at the innermost value where it occurred
+#[derive(Debug)]
+enum Error {
+    Custom(String),
+    Located(BencodeDecoderError),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Custom(message) => write!(f, "{message}"),
+            Error::Located(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl de::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Error::Custom(msg.to_string())
+    }
+}
+
+/// Deserializer of the value at `position` of validated data
+struct Deserializer<'a, 'de> {
+    data: &'de [u8],
+    position: usize,
+    /// Path to the value, for errors
+    path: &'a mut Vec<PathSegment>,
+}
+
+impl<'de> Deserializer<'_, 'de> {
+    fn value(&self) -> Result<BencodeRef<'de>, Error> {
+        read_value(self.data, &mut self.position.clone())
+            .ok_or_else(|| Error::Custom(String::from("invalid bencode")))
+    }
+
+    /// Add the offset and the path of the value to an error that is not located yet
+    fn locate(&self, error: Error) -> Error {
+        match error {
+            Error::Custom(message) => Error::Located(BencodeDecoderError {
+                kind: ErrorKind::Custom(message),
+                offset: self.position,
+                path: format_path(self.data, self.path),
+            }),
+            located => located,
+        }
+    }
+}
+
+impl<'de> de::Deserializer<'de> for Deserializer<'_, 'de> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        let result = match self.value()? {
+            BencodeRef::Integer(i) => visitor.visit_i64(i),
+            BencodeRef::ByteString(b) => match std::str::from_utf8(b) {
+                Ok(s) => visitor.visit_borrowed_str(s),
+                Err(_) => visitor.visit_borrowed_bytes(b),
+            },
+            BencodeRef::List(_) => visitor.visit_seq(Access {
+                data: self.data,
+                position: self.position + 1, // after 'l'
+                index: 0,
+                key: None,
+                path: self.path,
+            }),
+            BencodeRef::Dictionary(_) => visitor.visit_map(Access {
+                data: self.data,
+                position: self.position + 1, // after 'd'
+                index: 0,
+                key: None,
+                path: self.path,
+            }),
+        };
+        result.map_err(|e| self.locate(e))
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        let result = match self.value()? {
+            BencodeRef::Integer(0) => visitor.visit_bool(false),
+            BencodeRef::Integer(1) => visitor.visit_bool(true),
+            _ => return self.deserialize_any(visitor),
+        };
+        result.map_err(|e| self.locate(e))
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.value()? {
+            BencodeRef::ByteString(b) => {
+                visitor.visit_borrowed_bytes(b).map_err(|e| self.locate(e))
+            }
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_bytes(visitor)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        // a missing key is `None`, a present one is always `Some`
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        let result = match self.value()? {
+            BencodeRef::ByteString(b) => match std::str::from_utf8(b) {
+                Ok(variant) => visitor.visit_enum(variant.into_deserializer()),
+                Err(_) => Err(Error::Custom(String::from("invalid UTF-8 in enum variant"))),
+            },
+            _ => Err(Error::Custom(String::from(
+                "only unit variants written as strings are supported",
+            ))),
+        };
+        result.map_err(|e| self.locate(e))
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        // the value is already validated, there is no need to read it
+        visitor.visit_unit()
+    }
+
+    serde::forward_to_deserialize_any! {
+        i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        unit unit_struct seq tuple tuple_struct map struct identifier
+    }
+}
+
+/// Access to the elements of a list or the entries of a dictionary
+struct Access<'a, 'de> {
+    data: &'de [u8],
+    /// Position of the next element, key or value
+    position: usize,
+    /// Index of the next element of a list
+    index: usize,
+    /// Key of the value to read, for dictionaries
+    key: Option<std::ops::Range<usize>>,
+    path: &'a mut Vec<PathSegment>,
+}
+
+impl<'de> Access<'_, 'de> {
+    /// Deserialize the value at the current position and move after it
+    fn next<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+        segment: PathSegment,
+    ) -> Result<T::Value, Error> {
+        let start = self.position;
+        read_value(self.data, &mut self.position)
+            .ok_or_else(|| Error::Custom(String::from("invalid bencode")))?;
+        self.path.push(segment);
+        let result = seed.deserialize(Deserializer {
+            data: self.data,
+            position: start,
+            path: self.path,
+        });
+        self.path.pop();
+        result
+    }
+
+    fn at_end(&self) -> bool {
+        self.data.get(self.position).is_none_or(|b| *b == b'e')
+    }
+}
+
+impl<'de> SeqAccess<'de> for Access<'_, 'de> {
+    type Error = Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, Error> {
+        if self.at_end() {
+            return Ok(None);
+        }
+        let index = self.index;
+        self.index += 1;
+        self.next(seed, PathSegment::Index(index)).map(Some)
+    }
+}
+
+impl<'de> MapAccess<'de> for Access<'_, 'de> {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Error> {
+        if self.at_end() {
+            return Ok(None);
+        }
+        let start = self.position;
+        let key = read_value(self.data, &mut self.position)
+            .and_then(|key| key.as_bytes())
+            .ok_or_else(|| Error::Custom(String::from("invalid bencode")))?;
+        // the key is a slice of data
+        let key_start = self.position - key.len();
+        self.key = Some(key_start..self.position);
+        seed.deserialize(Deserializer {
+            data: self.data,
+            position: start,
+            path: self.path,
+        })
+        .map(Some)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Error> {
+        let key = self.key.take().unwrap_or_default();
+        self.next(seed, PathSegment::Key(key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    enum Event {
+        #[serde(rename = "started")]
+        Started,
+        #[serde(rename = "stopped")]
+        Stopped,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct File<'a> {
+        length: u64,
+        #[serde(borrow)]
+        path: Vec<&'a str>,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Root<'a> {
+        #[serde(borrow)]
+        name: &'a [u8],
+        #[serde(rename = "piece length")]
+        piece_length: u32,
+        private: Option<bool>,
+        comment: Option<String>,
+        files: Vec<File<'a>>,
+        event: Event,
+    }
+
+    #[test]
+    fn test_from_bytes() {
+        let data = b"d5:event7:started5:filesld6:lengthi10e4:pathl1:a1:beee4:name3:\xff\xfe\xfd\
+            12:piece lengthi16384e7:privatei1e6:unusedd1:xli1eeee";
+        let root: Root = from_bytes(data).unwrap();
+        assert_eq!(
+            root,
+            Root {
+                name: b"\xff\xfe\xfd",
+                piece_length: 16384,
+                private: Some(true),
+                comment: None,
+                files: vec![File {
+                    length: 10,
+                    path: vec!["a", "b"]
+                }],
+                event: Event::Started,
+            }
+        );
+        assert_eq!(from_bytes::<Event>(b"7:stopped").unwrap(), Event::Stopped);
+        let (event, value) = from_bytes_with_ref::<Event>(b"7:started", Limits::default()).unwrap();
+        assert_eq!(
+            (event, value),
+            (Event::Started, BencodeRef::ByteString(b"started"))
+        );
+        assert_eq!(
+            from_bytes::<std::collections::BTreeMap<String, i64>>(b"d1:ai1e1:bi-2ee").unwrap(),
+            [(String::from("a"), 1), (String::from("b"), -2)].into()
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        // negative length in the second file
+        let data = b"d5:event7:started5:filesld6:lengthi10e4:pathl1:aeed6:lengthi-1e4:pathl1:beee\
+            4:name1:n12:piece lengthi1ee";
+        let error = from_bytes::<Root>(data).unwrap_err();
+        assert!(matches!(error.kind, ErrorKind::Custom(_)));
+        assert_eq!(error.path, "files[1].length");
+        assert_eq!(error.offset, 59);
+
+        // missing field
+        let error = from_bytes::<File>(b"d4:pathlee").unwrap_err();
+        assert_eq!(error.to_string(), "missing field `length` at byte 0");
+
+        // invalid input is reported by the decoder
+        let error = from_bytes::<File>(b"d4:pathle").unwrap_err();
+        assert_eq!(error.kind, ErrorKind::UnexpectedEndOfInput);
+    }
+}
@@ -0,0 +1,637 @@
+// Serde serializer, to write typed structs as bencode. Values are built as a `BencodeValue` and encoded with
+// `encode_bencode_value`, so dictionary keys are sorted whatever the order of the fields.
+//
+// Bencode has no null: `None` and `()` are skipped in structs and maps and rejected in lists. Booleans are written as
+// integers 0 or 1, unit enum variants as strings and other variants as a dictionary with a single key, the variant.
+// Floats and integers that do not fit in an `i64` are rejected.
+use std::collections::BTreeMap;
+use std::fmt;
+
+use serde::Serialize;
+use serde::ser::{self, Impossible};
+
+use super::{BencodeValue, encode_bencode_value};
+
+/// Serialization error
+#[derive(Debug, PartialEq)]
+pub struct Error(String);
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl ser::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Error(msg.to_string())
+    }
+}
+
+/// Serialize a type to a bencode value
+pub fn to_value<T: Serialize + ?Sized>(value: &T) -> Result<BencodeValue, Error> {
+    value
+        .serialize(Serializer)?
+        .ok_or_else(|| Error(String::from("cannot serialize a missing value")))
+}
+
+/// Serialize a type to bencoded data
+pub fn to_bytes<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, Error> {
+    let value = to_value(value)?;
+    let mut buffer = Vec::new();
+    encode_bencode_value(&value, &mut buffer).map_err(|e| Error(e.to_string()))?;
+    Ok(buffer)
+}
+
+/// Serializer to a `BencodeValue`, `None` is a value that is not written
+struct Serializer;
+
+fn integer<T: TryInto<i64>>(value: T) -> Result<Option<BencodeValue>, Error> {
+    value
+        .try_into()
+        .map(|i| Some(BencodeValue::Integer(i)))
+        .map_err(|_| Error(String::from("integer is too large")))
+}
+
+fn byte_string(bytes: &[u8]) -> Result<Option<BencodeValue>, Error> {
+    Ok(Some(BencodeValue::ByteString(bytes.to_vec())))
+}
+
+/// Dictionary with a single key, for enum variants with values
+fn variant(name: &str, value: BencodeValue) -> Option<BencodeValue> {
+    Some(BencodeValue::Dictionary(BTreeMap::from([(
+        name.as_bytes().to_vec(),
+        value,
+    )])))
+}
+
+impl ser::Serializer for Serializer {
+    type Ok = Option<BencodeValue>;
+    type Error = Error;
+    type SerializeSeq = List;
+    type SerializeTuple = List;
+    type SerializeTupleStruct = List;
+    type SerializeTupleVariant = List;
+    type SerializeMap = Dictionary;
+    type SerializeStruct = Dictionary;
+    type SerializeStructVariant = Dictionary;
+
+    fn serialize_bool(self, v: bool) -> Result<Self::Ok, Error> {
+        integer(v as i64)
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Self::Ok, Error> {
+        integer(v)
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Self::Ok, Error> {
+        integer(v)
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Self::Ok, Error> {
+        integer(v)
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Self::Ok, Error> {
+        integer(v)
+    }
+
+    fn serialize_i128(self, v: i128) -> Result<Self::Ok, Error> {
+        integer(v)
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Self::Ok, Error> {
+        integer(v)
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Self::Ok, Error> {
+        integer(v)
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Self::Ok, Error> {
+        integer(v)
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Self::Ok, Error> {
+        integer(v)
+    }
+
+    fn serialize_u128(self, v: u128) -> Result<Self::Ok, Error> {
+        integer(v)
+    }
+
+    fn serialize_f32(self, _v: f32) -> Result<Self::Ok, Error> {
+        Err(Error(String::from("floats are not supported")))
+    }
+
+    fn serialize_f64(self, _v: f64) -> Result<Self::Ok, Error> {
+        Err(Error(String::from("floats are not supported")))
+    }
+
+    fn serialize_char(self, v: char) -> Result<Self::Ok, Error> {
+        byte_string(v.encode_utf8(&mut [0; 4]).as_bytes())
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Self::Ok, Error> {
+        byte_string(v.as_bytes())
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Self::Ok, Error> {
+        byte_string(v)
+    }
+
+    fn serialize_none(self) -> Result<Self::Ok, Error> {
+        Ok(None)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Self::Ok, Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Self::Ok, Error> {
+        Ok(None)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Self::Ok, Error> {
+        Ok(None)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<Self::Ok, Error> {
+        byte_string(variant.as_bytes())
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<Self::Ok, Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant_name: &'static str,
+        value: &T,
+    ) -> Result<Self::Ok, Error> {
+        Ok(variant(variant_name, to_value(value)?))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<Self::SerializeSeq, Error> {
+        Ok(List {
+            variant: None,
+            items: Vec::with_capacity(len.unwrap_or(0)),
+        })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<Self::SerializeTuple, Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeTupleStruct, Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeTupleVariant, Error> {
+        Ok(List {
+            variant: Some(variant),
+            items: Vec::with_capacity(len),
+        })
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, Error> {
+        Ok(Dictionary {
+            variant: None,
+            entries: BTreeMap::new(),
+            key: None,
+        })
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeStruct, Error> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, Error> {
+        Ok(Dictionary {
+            variant: Some(variant),
+            entries: BTreeMap::new(),
+            key: None,
+        })
+    }
+}
+
+/// List being serialized, `variant` is the enum variant of a tuple variant
+struct List {
+    variant: Option<&'static str>,
+    items: Vec<BencodeValue>,
+}
+
+impl List {
+    fn push<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        match value.serialize(Serializer)? {
+            Some(value) => self.items.push(value),
+            None => return Err(Error(String::from("lists cannot contain missing values"))),
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<Option<BencodeValue>, Error> {
+        let list = BencodeValue::List(self.items);
+        Ok(match self.variant {
+            Some(name) => variant(name, list),
+            None => Some(list),
+        })
+    }
+}
+
+impl ser::SerializeSeq for List {
+    type Ok = Option<BencodeValue>;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Error> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTuple for List {
+    type Ok = Option<BencodeValue>;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Error> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTupleStruct for List {
+    type Ok = Option<BencodeValue>;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Error> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTupleVariant for List {
+    type Ok = Option<BencodeValue>;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Error> {
+        self.finish()
+    }
+}
+
+/// Dictionary being serialized, `variant` is the enum variant of a struct variant
+struct Dictionary {
+    variant: Option<&'static str>,
+    entries: BTreeMap<Vec<u8>, BencodeValue>,
+    /// Key of the next value of a map
+    key: Option<Vec<u8>>,
+}
+
+impl Dictionary {
+    fn insert<T: Serialize + ?Sized>(&mut self, key: Vec<u8>, value: &T) -> Result<(), Error> {
+        if let Some(value) = value.serialize(Serializer)? {
+            self.entries.insert(key, value);
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<Option<BencodeValue>, Error> {
+        let dictionary = BencodeValue::Dictionary(self.entries);
+        Ok(match self.variant {
+            Some(name) => variant(name, dictionary),
+            None => Some(dictionary),
+        })
+    }
+}
+
+impl ser::SerializeMap for Dictionary {
+    type Ok = Option<BencodeValue>;
+    type Error = Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), Error> {
+        self.key = Some(key.serialize(KeySerializer)?);
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        let key = self
+            .key
+            .take()
+            .ok_or_else(|| Error(String::from("value without a key")))?;
+        self.insert(key, value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Error> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeStruct for Dictionary {
+    type Ok = Option<BencodeValue>;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.insert(key.as_bytes().to_vec(), value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Error> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeStructVariant for Dictionary {
+    type Ok = Option<BencodeValue>;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.insert(key.as_bytes().to_vec(), value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Error> {
+        self.finish()
+    }
+}
+
+/// Serializer of dictionary keys, they must be strings or bytes
+struct KeySerializer;
+
+fn key_error() -> Error {
+    Error(String::from("dictionary keys must be strings"))
+}
+
+impl ser::Serializer for KeySerializer {
+    type Ok = Vec<u8>;
+    type Error = Error;
+    type SerializeSeq = Impossible<Vec<u8>, Error>;
+    type SerializeTuple = Impossible<Vec<u8>, Error>;
+    type SerializeTupleStruct = Impossible<Vec<u8>, Error>;
+    type SerializeTupleVariant = Impossible<Vec<u8>, Error>;
+    type SerializeMap = Impossible<Vec<u8>, Error>;
+    type SerializeStruct = Impossible<Vec<u8>, Error>;
+    type SerializeStructVariant = Impossible<Vec<u8>, Error>;
+
+    fn serialize_str(self, v: &str) -> Result<Vec<u8>, Error> {
+        Ok(v.as_bytes().to_vec())
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Vec<u8>, Error> {
+        Ok(v.to_vec())
+    }
+
+    fn serialize_char(self, v: char) -> Result<Vec<u8>, Error> {
+        Ok(v.to_string().into_bytes())
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<Vec<u8>, Error> {
+        Ok(variant.as_bytes().to_vec())
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<Vec<u8>, Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_bool(self, _v: bool) -> Result<Vec<u8>, Error> {
+        Err(key_error())
+    }
+
+    fn serialize_i8(self, _v: i8) -> Result<Vec<u8>, Error> {
+        Err(key_error())
+    }
+
+    fn serialize_i16(self, _v: i16) -> Result<Vec<u8>, Error> {
+        Err(key_error())
+    }
+
+    fn serialize_i32(self, _v: i32) -> Result<Vec<u8>, Error> {
+        Err(key_error())
+    }
+
+    fn serialize_i64(self, _v: i64) -> Result<Vec<u8>, Error> {
+        Err(key_error())
+    }
+
+    fn serialize_u8(self, _v: u8) -> Result<Vec<u8>, Error> {
+        Err(key_error())
+    }
+
+    fn serialize_u16(self, _v: u16) -> Result<Vec<u8>, Error> {
+        Err(key_error())
+    }
+
+    fn serialize_u32(self, _v: u32) -> Result<Vec<u8>, Error> {
+        Err(key_error())
+    }
+
+    fn serialize_u64(self, _v: u64) -> Result<Vec<u8>, Error> {
+        Err(key_error())
+    }
+
+    fn serialize_f32(self, _v: f32) -> Result<Vec<u8>, Error> {
+        Err(key_error())
+    }
+
+    fn serialize_f64(self, _v: f64) -> Result<Vec<u8>, Error> {
+        Err(key_error())
+    }
+
+    fn serialize_none(self) -> Result<Vec<u8>, Error> {
+        Err(key_error())
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, _value: &T) -> Result<Vec<u8>, Error> {
+        Err(key_error())
+    }
+
+    fn serialize_unit(self) -> Result<Vec<u8>, Error> {
+        Err(key_error())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Vec<u8>, Error> {
+        Err(key_error())
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<Vec<u8>, Error> {
+        Err(key_error())
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, Error> {
+        Err(key_error())
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, Error> {
+        Err(key_error())
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct, Error> {
+        Err(key_error())
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, Error> {
+        Err(key_error())
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, Error> {
+        Err(key_error())
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStruct, Error> {
+        Err(key_error())
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, Error> {
+        Err(key_error())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    struct Response {
+        interval: u32,
+        #[serde(rename = "min interval")]
+        min_interval: Option<u32>,
+        #[serde(with = "serde_bytes_vec")]
+        peers: Vec<u8>,
+        complete: bool,
+        event: Event,
+    }
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    enum Event {
+        #[serde(rename = "started")]
+        Started,
+    }
+
+    /// `Vec<u8>` as a byte string instead of a list of integers
+    mod serde_bytes_vec {
+        use serde::{Deserialize, Deserializer, Serializer};
+
+        pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.serialize_bytes(bytes)
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Vec<u8>, D::Error> {
+            <&[u8]>::deserialize(deserializer).map(<[u8]>::to_vec)
+        }
+    }
+
+    #[test]
+    fn test_to_bytes() {
+        let response = Response {
+            interval: 1800,
+            min_interval: None,
+            peers: vec![0xff, 0],
+            complete: true,
+            event: Event::Started,
+        };
+        // keys are sorted, None is skipped
+        let data = to_bytes(&response).unwrap();
+        assert_eq!(
+            data,
+            b"d8:completei1e5:event7:started8:intervali1800e5:peers2:\xff\x00e"
+        );
+        assert_eq!(
+            crate::bencode::from_bytes::<Response>(&data).unwrap(),
+            response
+        );
+
+        assert_eq!(to_bytes(&vec![1, -2]).unwrap(), b"li1ei-2ee");
+        assert!(to_bytes(&u64::MAX).is_err());
+        assert!(to_bytes(&1.5).is_err());
+        assert!(to_bytes(&vec![None, Some(1)]).is_err());
+        assert!(to_bytes(&BTreeMap::from([(1, 2)])).is_err());
+    }
+}
@@ -1,9 +1,16 @@
 use std::str::FromStr;
 
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
 use std::path::PathBuf;
 use toml::Value;
 use tracing::{error, info, warn};
 
+use crate::bencode::Limits;
+use crate::export::{Format, StatsOutput};
+use crate::hooks;
+use crate::notify::{KINDS, Webhook, WebhookFormat};
+
 // use crate::json_output;
 
 #[derive(Debug, Clone)]
@@ -24,10 +31,131 @@ pub struct Config {
     /// Directory where torrents are saved. Default is in the working directory.
     pub torrent_dir: PathBuf,
     // pub key_refresh_every: u16,
-    /// Output file path for the JSON file.
-    /// You may want somethink like `/var/www/ratio_up.json` to expose it on your web server.
-    pub output_stats: Option<PathBuf>,
+    /// Stats files, i.e. a JSON file like `/var/www/ratio_up.json` to expose it on your web server.
+    pub output_stats: Vec<StatsOutput>,
+    /// Add the metadata of the torrents (files, source, comment...) to the JSON stats
+    pub stats_metadata: bool,
+    /// File where the history of the announces is saved, it is only kept in memory without it
+    pub history_file: Option<PathBuf>,
+    /// The stats file is written when its content changes, or when it is older than this (in seconds)
+    pub stats_interval: u64,
+    /// Enable the embedded HTTP server (control API)
+    pub http_enabled: bool,
+    /// Address the embedded HTTP server listens on. Default is localhost only.
+    pub http_address: SocketAddr,
+    /// Optional basic authentication for the embedded HTTP server, both username and password must be set
+    pub http_username: Option<String>,
+    pub http_password: Option<String>,
+    /// Do not put torrent names in Prometheus metrics labels, only info hashes
+    pub metrics_hide_names: bool,
+    /// Move files that cannot be loaded to `torrent_dir/.invalid/`, with the error in a `.error.txt` file
+    pub quarantine_invalid: bool,
+    /// Move files of finished torrents (stop rule reached or not registered) to `torrent_dir/.done/` instead of pausing them
+    pub quarantine_done: bool,
+    /// Settings by category, the category of a torrent is the name of its first subfolder in `torrent_dir`
+    pub categories: BTreeMap<String, CategoryConfig>,
+    /// Limits when decoding torrent files and tracker responses
+    pub bencode_limits: Limits,
+    /// Webhooks notified of the important events (`[[webhooks]]`)
+    pub webhooks: Vec<Webhook>,
+    /// Notify when a torrent has this number of announce errors, 0 to disable
+    pub notify_error_threshold: u16,
+    /// Shell commands run on the lifecycle events (`[hooks]`), by event
+    pub hooks: BTreeMap<String, String>,
+    /// Hooks are killed after this time (in seconds)
+    pub hook_timeout: u64,
+    /// Failure reasons of the trackers meaning that a torrent is not registered, case insensitive
+    pub unregistered_patterns: Vec<String>,
+    /// What to do when a tracker tells that a torrent is not registered
+    pub unregistered_action: UnregisteredAction,
+    /// Settings by tracker host (`[trackers."<host>"]`)
+    pub trackers: BTreeMap<String, TrackerConfig>,
+}
+
+/// Failure reasons of the trackers meaning that a torrent is not registered
+pub const UNREGISTERED_PATTERNS: [&str; 7] = [
+    "unregistered torrent",
+    "torrent not registered",
+    "torrent not found",
+    "unknown torrent",
+    "info_hash not found",
+    "infohash not found",
+    "torrent has been deleted",
+];
+
+/// What to do when a tracker tells that a torrent is not registered (`unregistered_action`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnregisteredAction {
+    /// Stop announcing to the tracker, the torrent is stopped when none of its trackers is left
+    DisableTracker,
+    /// Stop the torrent
+    StopTorrent,
+}
+
+/// Settings of a tracker (`[trackers."<host>"]`)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TrackerConfig {
+    /// Failure reasons meaning that a torrent is not registered, in addition to `unregistered_patterns`
+    pub unregistered_patterns: Vec<String>,
+}
+
+/// Settings of a category (`[categories.<name>]`), missing values fall back to the global ones
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CategoryConfig {
+    pub min_upload_rate: Option<u32>, //in byte
+    pub max_upload_rate: Option<u32>, //in byte
+    /// Fake client used to announce the torrents of the category
+    pub client: Option<String>,
+    /// Pause a torrent when its ratio (uploaded / size) reaches this value
+    pub max_ratio: Option<f64>,
+    /// Pause a torrent when it has uploaded this amount of bytes
+    pub max_uploaded: Option<u64>,
+}
+
+impl CategoryConfig {
+    /// Upload rates of the category, based on the global ones
+    pub fn rates(&self, global: Rates) -> Rates {
+        Rates {
+            min_upload_rate: self.min_upload_rate.unwrap_or(global.min_upload_rate),
+            max_upload_rate: self.max_upload_rate.unwrap_or(global.max_upload_rate),
+        }
+    }
+
+    /// Tells if a stop rule is reached for the given uploaded bytes and torrent size
+    pub fn should_stop(&self, uploaded: u64, length: u64) -> bool {
+        if self.max_uploaded.is_some_and(|max| uploaded >= max) {
+            return true;
+        }
+        // the size of a magnet link may be unknown
+        length > 0
+            && self
+                .max_ratio
+                .is_some_and(|max| uploaded as f64 / length as f64 >= max)
+    }
+}
+
+/// Upload rates that can be changed while RatioUp is running (i.e. from the control API)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rates {
+    pub min_upload_rate: u32, //in byte
+    pub max_upload_rate: u32, //in byte
+}
+
+impl Rates {
+    pub fn is_valid(&self) -> bool {
+        self.min_upload_rate <= self.max_upload_rate
+    }
 }
+
+impl From<&Config> for Rates {
+    fn from(config: &Config) -> Self {
+        Rates {
+            min_upload_rate: config.min_upload_rate,
+            max_upload_rate: config.max_upload_rate,
+        }
+    }
+}
+
 impl Default for Config {
     fn default() -> Self {
         Config {
@@ -43,7 +171,26 @@ impl Default for Config {
             //client: fake_torrent_client::Client::from(fake_torrent_client::clients::ClientVersion::Qbittorrent_4_4_2),
             // key_refresh_every: 0,
             client: String::from("Transmission_3_00"),
-            output_stats: None,
+            output_stats: Vec::new(),
+            stats_metadata: false,
+            stats_interval: 300,
+            history_file: None,
+            http_enabled: false,
+            http_address: SocketAddr::from(([127, 0, 0, 1], 8070)),
+            http_username: None,
+            http_password: None,
+            metrics_hide_names: false,
+            quarantine_invalid: false,
+            quarantine_done: false,
+            categories: BTreeMap::new(),
+            bencode_limits: Limits::default(),
+            webhooks: Vec::new(),
+            notify_error_threshold: 5,
+            hooks: BTreeMap::new(),
+            hook_timeout: 30,
+            unregistered_patterns: UNREGISTERED_PATTERNS.map(String::from).to_vec(),
+            unregistered_action: UnregisteredAction::DisableTracker,
+            trackers: BTreeMap::new(),
         }
     }
 }
@@ -141,11 +288,223 @@ impl Config {
                     }
                 }
 
+                // a file, or a list of files and `{ path = "...", format = "..." }` tables
                 if let Some(value) = root_table.get("output_stats") {
+                    let outputs = match value.as_array() {
+                        Some(outputs) => outputs.iter().collect(),
+                        None => vec![value],
+                    };
+                    for output in outputs {
+                        if let Some(output) = parse_stats_output(output) {
+                            config.output_stats.push(output);
+                        } else {
+                            error!("Invalid output_stats: {output}");
+                        }
+                    }
+                }
+
+                if let Some(value) = root_table.get("stats_metadata") {
+                    if let Some(enabled) = value.as_bool() {
+                        config.stats_metadata = enabled;
+                    } else {
+                        error!("stats_metadata is not a boolean");
+                    }
+                }
+
+                if let Some(value) = root_table.get("history_file") {
                     if let Some(path) = value.as_str() {
-                        config.output_stats = Some(PathBuf::from(path));
+                        config.history_file = Some(PathBuf::from(path));
                     } else {
-                        error!("Invalid output_stats");
+                        error!("Invalid history_file");
+                    }
+                }
+
+                if let Some(value) = root_table.get("stats_interval") {
+                    match value.as_integer().map(u64::try_from) {
+                        Some(Ok(seconds)) => config.stats_interval = seconds,
+                        _ => error!("stats_interval is not a positive integer"),
+                    }
+                }
+
+                if let Some(value) = root_table.get("http_enabled") {
+                    if let Some(enabled) = value.as_bool() {
+                        config.http_enabled = enabled;
+                    } else {
+                        error!("http_enabled is not a boolean");
+                    }
+                }
+
+                if let Some(value) = root_table.get("http_address") {
+                    if let Some(address) = value.as_str() {
+                        match SocketAddr::from_str(address) {
+                            Ok(address) => config.http_address = address,
+                            Err(e) => error!("Invalid http_address: {e}"),
+                        }
+                    } else {
+                        error!("http_address is not a string");
+                    }
+                }
+
+                if let Some(value) = root_table.get("http_username") {
+                    if let Some(username) = value.as_str() {
+                        config.http_username = Some(String::from(username));
+                    } else {
+                        error!("http_username is not a string");
+                    }
+                }
+
+                if let Some(value) = root_table.get("http_password") {
+                    if let Some(password) = value.as_str() {
+                        config.http_password = Some(String::from(password));
+                    } else {
+                        error!("http_password is not a string");
+                    }
+                }
+
+                if let Some(value) = root_table.get("webhooks") {
+                    if let Some(webhooks) = value.as_array() {
+                        for value in webhooks {
+                            match parse_webhook(value) {
+                                Some(webhook) => config.webhooks.push(webhook),
+                                None => error!("Invalid webhook: {value}"),
+                            }
+                        }
+                    } else {
+                        error!("webhooks is not an array of tables");
+                    }
+                }
+
+                if let Some(value) = root_table.get("notify_error_threshold") {
+                    match value.as_integer().map(u16::try_from) {
+                        Some(Ok(threshold)) => config.notify_error_threshold = threshold,
+                        _ => error!("notify_error_threshold is not a positive integer"),
+                    }
+                }
+
+                if let Some(value) = root_table.get("hooks") {
+                    if let Some(hooks) = value.as_table() {
+                        for (event, value) in hooks {
+                            match value.as_str() {
+                                Some(command) if hooks::EVENTS.contains(&event.as_str()) => {
+                                    config.hooks.insert(event.clone(), command.to_string());
+                                }
+                                Some(_) => error!(
+                                    "Unknown hook {event}, expected one of {}",
+                                    hooks::EVENTS.join(", ")
+                                ),
+                                None => error!("Hook {event} is not a string"),
+                            }
+                        }
+                    } else {
+                        error!("hooks is not a table");
+                    }
+                }
+
+                if let Some(value) = root_table.get("hook_timeout") {
+                    match value.as_integer().map(u64::try_from) {
+                        Some(Ok(seconds)) if seconds > 0 => config.hook_timeout = seconds,
+                        _ => error!("hook_timeout is not a positive integer"),
+                    }
+                }
+
+                if let Some(value) = root_table.get("metrics_hide_names") {
+                    if let Some(hide) = value.as_bool() {
+                        config.metrics_hide_names = hide;
+                    } else {
+                        error!("metrics_hide_names is not a boolean");
+                    }
+                }
+
+                if let Some(value) = root_table.get("quarantine_invalid") {
+                    if let Some(enabled) = value.as_bool() {
+                        config.quarantine_invalid = enabled;
+                    } else {
+                        error!("quarantine_invalid is not a boolean");
+                    }
+                }
+
+                if let Some(value) = root_table.get("quarantine_done") {
+                    if let Some(enabled) = value.as_bool() {
+                        config.quarantine_done = enabled;
+                    } else {
+                        error!("quarantine_done is not a boolean");
+                    }
+                }
+
+                if let Some(value) = root_table.get("unregistered_patterns") {
+                    match parse_patterns(value) {
+                        Some(patterns) => config.unregistered_patterns = patterns,
+                        None => error!("unregistered_patterns is not a list of strings"),
+                    }
+                }
+
+                if let Some(value) = root_table.get("unregistered_action") {
+                    match value.as_str() {
+                        Some("disable_tracker") => {
+                            config.unregistered_action = UnregisteredAction::DisableTracker
+                        }
+                        Some("stop_torrent") => {
+                            config.unregistered_action = UnregisteredAction::StopTorrent
+                        }
+                        _ => error!("unregistered_action is not disable_tracker or stop_torrent"),
+                    }
+                }
+
+                if let Some(value) = root_table.get("trackers") {
+                    if let Some(trackers) = value.as_table() {
+                        for (host, value) in trackers {
+                            let mut tracker = TrackerConfig::default();
+                            match value.get("unregistered_patterns").map(parse_patterns) {
+                                Some(Some(patterns)) => tracker.unregistered_patterns = patterns,
+                                Some(None) => error!(
+                                    "unregistered_patterns of tracker {host} is not a list of strings"
+                                ),
+                                None => {}
+                            }
+                            config.trackers.insert(host.to_lowercase(), tracker);
+                        }
+                    } else {
+                        error!("trackers is not a table");
+                    }
+                }
+
+                for (key, limit) in [
+                    ("bencode_max_depth", &mut config.bencode_limits.max_depth),
+                    ("bencode_max_size", &mut config.bencode_limits.max_size),
+                    (
+                        "bencode_max_string_length",
+                        &mut config.bencode_limits.max_string_length,
+                    ),
+                ] {
+                    if let Some(value) = root_table.get(key) {
+                        match value.as_integer().map(usize::try_from) {
+                            Some(Ok(value)) if value > 0 => *limit = value,
+                            _ => error!("{key} is not a positive integer"),
+                        }
+                    }
+                }
+
+                if let Some(value) = root_table.get("bencode_strict") {
+                    if let Some(strict) = value.as_bool() {
+                        config.bencode_limits.strict = strict;
+                    } else {
+                        error!("bencode_strict is not a boolean");
+                    }
+                }
+
+                if let Some(value) = root_table.get("categories") {
+                    if let Some(categories) = value.as_table() {
+                        for (name, value) in categories {
+                            if let Some(table) = value.as_table() {
+                                config
+                                    .categories
+                                    .insert(name.clone(), parse_category(name, table));
+                            } else {
+                                error!("Category {name} is not a table");
+                            }
+                        }
+                    } else {
+                        error!("categories is not a table");
                     }
                 }
             }
@@ -155,6 +514,14 @@ impl Config {
             }
         };
 
+        if config.http_username.is_some() != config.http_password.is_some() {
+            warn!(
+                "Both http_username and http_password are required, basic authentication is disabled"
+            );
+            config.http_username = None;
+            config.http_password = None;
+        }
+
         if !config.speeds_ok() {
             warn!(
                 "Min upload rate ({}) is greater than max upload rate ({}), switching values",
@@ -167,37 +534,191 @@ impl Config {
     }
 
     fn speeds_ok(&self) -> bool {
-        self.min_upload_rate <= self.max_upload_rate
+        Rates::from(self).is_valid()
+    }
+
+    /// Tells if the failure reason of a tracker means that the torrent is not registered
+    pub fn is_unregistered(&self, host: &str, reason: &str) -> bool {
+        let reason = reason.to_lowercase();
+        let host_patterns = self
+            .trackers
+            .get(&host.to_lowercase())
+            .map(|t| t.unregistered_patterns.as_slice())
+            .unwrap_or_default();
+        self.unregistered_patterns
+            .iter()
+            .chain(host_patterns)
+            .any(|pattern| reason.contains(pattern.as_str()))
+    }
+
+    /// Get the settings of a category, if it has some
+    pub fn category(&self, name: Option<&str>) -> Option<&CategoryConfig> {
+        name.and_then(|n| self.categories.get(n))
     }
 }
 
-/// Init the client from the configuration and returns the interval to refresh client key if applicable
-pub async fn init_client(config: &Config) -> Option<u16> {
+/// Parse a stats file of `output_stats`: a path, its format is guessed from the extension, or a table with a `path`
+/// and a `format`
+fn parse_stats_output(value: &Value) -> Option<StatsOutput> {
+    if let Some(path) = value.as_str() {
+        let path = PathBuf::from(path);
+        return Some(StatsOutput {
+            format: Format::from_path(&path),
+            path,
+        });
+    }
+    let table = value.as_table()?;
+    let path = PathBuf::from(table.get("path")?.as_str()?);
+    let format = match table.get("format") {
+        Some(format) => Format::from_name(format.as_str()?)?,
+        None => Format::from_path(&path),
+    };
+    Some(StatsOutput { path, format })
+}
+
+/// Parse a list of patterns, they are lowercased to be case insensitive
+fn parse_patterns(value: &Value) -> Option<Vec<String>> {
+    value
+        .as_array()?
+        .iter()
+        .map(|pattern| pattern.as_str().map(str::to_lowercase))
+        .collect()
+}
+
+/// Parse a `[[webhooks]]` table: a `url`, an optional `format` (JSON by default) and the optional `events` to notify
+fn parse_webhook(value: &Value) -> Option<Webhook> {
+    let table = value.as_table()?;
+    let url = table.get("url")?.as_str()?.to_string();
+    let format = match table.get("format") {
+        Some(format) => WebhookFormat::from_name(format.as_str()?)?,
+        None => WebhookFormat::Json,
+    };
+    let mut events = Vec::new();
+    if let Some(value) = table.get("events") {
+        for event in value.as_array()? {
+            let event = event.as_str()?;
+            if !KINDS.contains(&event) {
+                error!(
+                    "Unknown webhook event {event}, expected one of {}",
+                    KINDS.join(", ")
+                );
+                return None;
+            }
+            events.push(event.to_string());
+        }
+    }
+    Some(Webhook {
+        url,
+        format,
+        events,
+    })
+}
+
+/// Parse a `[categories.<name>]` table, invalid values are ignored
+fn parse_category(name: &str, table: &toml::value::Table) -> CategoryConfig {
+    let mut category = CategoryConfig::default();
+
+    if let Some(value) = table.get("min_upload_rate") {
+        if let Some(rate) = value.as_integer() {
+            category.min_upload_rate = Some(rate as u32);
+        } else {
+            error!("Invalid min upload rate for category {name}");
+        }
+    }
+    if let Some(value) = table.get("max_upload_rate") {
+        if let Some(rate) = value.as_integer() {
+            category.max_upload_rate = Some(rate as u32);
+        } else {
+            error!("Invalid max upload rate for category {name}");
+        }
+    }
+    if let (Some(min), Some(max)) = (category.min_upload_rate, category.max_upload_rate)
+        && min > max
+    {
+        warn!(
+            "Min upload rate ({min}) is greater than max upload rate ({max}) for category {name}, switching values"
+        );
+        category.min_upload_rate = Some(max);
+        category.max_upload_rate = Some(min);
+    }
+
+    if let Some(value) = table.get("client") {
+        if let Some(client) = value.as_str() {
+            category.client = Some(String::from(client));
+        } else {
+            error!("Client is not a string for category {name}");
+        }
+    }
+
+    if let Some(value) = table.get("max_ratio") {
+        // accept `max_ratio = 2` as well as `max_ratio = 2.0`
+        match value.as_float().or(value.as_integer().map(|i| i as f64)) {
+            Some(ratio) if ratio > 0.0 => category.max_ratio = Some(ratio),
+            _ => error!("Invalid max_ratio for category {name}"),
+        }
+    }
+
+    if let Some(value) = table.get("max_uploaded") {
+        match value.as_integer() {
+            Some(bytes) if bytes > 0 => category.max_uploaded = Some(bytes as u64),
+            _ => error!("Invalid max_uploaded for category {name}"),
+        }
+    }
+
+    category
+}
+
+/// Build a fake client from its name, or the default one if it does not exist
+fn build_client(name: &str) -> fake_torrent_client::Client {
     let mut client = fake_torrent_client::Client::default();
-    match fake_torrent_client::clients::ClientVersion::from_str(&config.client) {
+    match fake_torrent_client::clients::ClientVersion::from_str(name) {
         Ok(selected) => {
             client.build(selected);
         }
         Err(e) => {
-            error!(
-                "Client {} does not exist, using default one: {e}",
-                config.client
-            );
+            error!("Client {name} does not exist, using default one: {e}");
         }
     }
     info!(
         "Client {} (key: {}, peer ID:{})",
         client.name, client.key, client.peer_id
     );
+    client
+}
+
+/// Init the client from the configuration and returns the interval to refresh client key if applicable
+pub async fn init_client(config: &Config) -> Option<u16> {
+    let client = build_client(&config.client);
     let key_interval = client.key_refresh_every;
     let mut guard = crate::CLIENT.write().await;
     *guard = Some(client);
     key_interval
 }
 
+/// Init the clients of the categories that have their own. It returns the categories with the interval to refresh
+/// their client key if applicable.
+pub async fn init_category_clients(config: &Config) -> Vec<(String, u16)> {
+    let mut key_intervals = Vec::new();
+    let mut clients = crate::CATEGORY_CLIENTS.write().await;
+    for (name, category) in &config.categories {
+        if let Some(client_name) = &category.client {
+            info!("Category {name}:");
+            let client = build_client(client_name);
+            if let Some(interval) = client.key_refresh_every {
+                key_intervals.push((name.clone(), interval));
+            }
+            clients.insert(name.clone(), client);
+        }
+    }
+    key_intervals
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::config::Config;
+    use crate::config::{CategoryConfig, Config, Rates, TrackerConfig};
+    use crate::export::{Format, StatsOutput};
+    use crate::notify::{Webhook, WebhookFormat};
+    use std::path::PathBuf;
 
     #[test]
     fn test_speed_ok() {
@@ -212,4 +733,128 @@ mod tests {
         cfg.max_upload_rate = 4096;
         assert!(!cfg.speeds_ok());
     }
+
+    #[test]
+    fn test_parse_category() {
+        let value: toml::Value = toml::from_str(
+            "min_upload_rate = 4096\nmax_upload_rate = 1024\nclient = \"Qbittorrent_4_4_2\"\nmax_ratio = 2\nmax_uploaded = -1",
+        )
+        .unwrap();
+        let category = super::parse_category("test", value.as_table().unwrap());
+        assert_eq!(category.min_upload_rate, Some(1024));
+        assert_eq!(category.max_upload_rate, Some(4096));
+        assert_eq!(category.client.as_deref(), Some("Qbittorrent_4_4_2"));
+        assert_eq!(category.max_ratio, Some(2.0));
+        assert_eq!(category.max_uploaded, None);
+    }
+
+    #[test]
+    fn test_parse_stats_output() {
+        let value: toml::Value = toml::from_str(
+            "output_stats = [\"/tmp/a.json\", \"b.prom\", { path = \"c.txt\", format = \"csv\" }, { path = \"d.htm\" }, \
+            { path = \"e\", format = \"xml\" }, 1]",
+        )
+        .unwrap();
+        let outputs: Vec<Option<StatsOutput>> = value["output_stats"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(super::parse_stats_output)
+            .collect();
+        let output = |path: &str, format| {
+            Some(StatsOutput {
+                path: PathBuf::from(path),
+                format,
+            })
+        };
+        assert_eq!(
+            outputs,
+            vec![
+                output("/tmp/a.json", Format::Json),
+                output("b.prom", Format::Prometheus),
+                output("c.txt", Format::Csv),
+                output("d.htm", Format::Html),
+                None,
+                None
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_webhook() {
+        let value: toml::Value = toml::from_str(
+            "webhooks = [{ url = \"https://ntfy.sh/ratioup\", format = \"ntfy\", events = [\"stop_rule\"] }, \
+            { url = \"http://localhost/hook\" }, { url = \"http://a\", format = \"slack\" }, \
+            { url = \"http://a\", events = [\"unknown\"] }, { format = \"json\" }]",
+        )
+        .unwrap();
+        let webhooks: Vec<Option<Webhook>> = value["webhooks"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(super::parse_webhook)
+            .collect();
+        assert_eq!(
+            webhooks,
+            vec![
+                Some(Webhook {
+                    url: String::from("https://ntfy.sh/ratioup"),
+                    format: WebhookFormat::Ntfy,
+                    events: vec![String::from("stop_rule")],
+                }),
+                Some(Webhook {
+                    url: String::from("http://localhost/hook"),
+                    format: WebhookFormat::Json,
+                    events: Vec::new(),
+                }),
+                None,
+                None,
+                None
+            ]
+        );
+    }
+
+    #[test]
+    fn test_is_unregistered() {
+        let mut config = Config::default();
+        assert!(config.is_unregistered("tracker.tld", "Unregistered torrent"));
+        assert!(!config.is_unregistered("tracker.tld", "Torrent was trumped"));
+        config.trackers.insert(
+            String::from("tracker.tld"),
+            TrackerConfig {
+                unregistered_patterns: vec![String::from("trumped")],
+            },
+        );
+        assert!(config.is_unregistered("Tracker.tld", "Torrent was trumped"));
+        assert!(!config.is_unregistered("other.tld", "Torrent was trumped"));
+    }
+
+    #[test]
+    fn test_category_rules() {
+        let global = Rates {
+            min_upload_rate: 1024,
+            max_upload_rate: 8192,
+        };
+        let category = CategoryConfig {
+            max_upload_rate: Some(2048),
+            max_ratio: Some(1.5),
+            ..Default::default()
+        };
+        assert_eq!(
+            category.rates(global),
+            Rates {
+                min_upload_rate: 1024,
+                max_upload_rate: 2048
+            }
+        );
+        assert!(!category.should_stop(100, 100));
+        assert!(category.should_stop(150, 100));
+        // unknown size
+        assert!(!category.should_stop(150, 0));
+        let category = CategoryConfig {
+            max_uploaded: Some(1000),
+            ..Default::default()
+        };
+        assert!(category.should_stop(1000, 0));
+    }
 }
@@ -0,0 +1,131 @@
+// Control file, read when RatioUp receives the SIGUSR1 signal: `kill -USR1 $(cat $XDG_RUNTIME_DIR/ratio_up.pid)`
+// Each line is a command with a torrent info hash (hexadecimal) or `*` for all torrents:
+//   pause <info hash>
+//   resume <info hash>
+// The file is removed once processed.
+use std::path::PathBuf;
+
+use tracing::{error, info, warn};
+
+use crate::TORRENTS;
+use crate::utils::info_hash_from_hex;
+use crate::watcher::set_paused;
+
+/// Name of the control file in the torrent directory
+pub const CONTROL_FILE: &str = "ratioup.control";
+
+#[derive(Debug, PartialEq)]
+enum Target {
+    All,
+    Torrent([u8; 20]),
+}
+
+#[derive(Debug, PartialEq)]
+enum Command {
+    Pause(Target),
+    Resume(Target),
+}
+
+fn parse_line(line: &str) -> Option<Command> {
+    let mut parts = line.split_whitespace();
+    let action = parts.next()?;
+    let target = match parts.next()? {
+        "*" => Target::All,
+        hash => Target::Torrent(info_hash_from_hex(hash)?),
+    };
+    if parts.next().is_some() {
+        return None;
+    }
+    match action.to_lowercase().as_str() {
+        "pause" => Some(Command::Pause(target)),
+        "resume" => Some(Command::Resume(target)),
+        _ => None,
+    }
+}
+
+async fn apply(target: Target, paused: bool) {
+    let hashes: Vec<[u8; 20]> = match target {
+        Target::Torrent(hash) => vec![hash],
+        Target::All => {
+            let list = TORRENTS.read().await;
+            let mut hashes = Vec::with_capacity(list.len());
+            for m in list.iter() {
+                hashes.push(m.lock().await.info_hash);
+            }
+            hashes
+        }
+    };
+    for hash in hashes {
+        if !set_paused(&hash, paused).await {
+            warn!("No torrent with hash {}", crate::utils::to_hex(&hash));
+        }
+    }
+}
+
+/// Read and apply the commands of the control file
+async fn process(path: &PathBuf) {
+    let content = match tokio::fs::read_to_string(path).await {
+        Ok(c) => c,
+        Err(e) => {
+            warn!("Cannot read control file {}: {e}", path.display());
+            return;
+        }
+    };
+    if let Err(e) = tokio::fs::remove_file(path).await {
+        error!("Cannot remove control file {}: {e}", path.display());
+    }
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match parse_line(line) {
+            Some(Command::Pause(target)) => apply(target, true).await,
+            Some(Command::Resume(target)) => apply(target, false).await,
+            None => warn!("Invalid control command: {line}"),
+        }
+    }
+}
+
+/// Wait for SIGUSR1 signals and process the control file each time
+#[cfg(unix)]
+pub async fn watch_signal(torrent_dir: PathBuf) {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let mut signals = match signal(SignalKind::user_defined1()) {
+        Ok(s) => s,
+        Err(e) => {
+            error!("Cannot listen to SIGUSR1: {e}");
+            return;
+        }
+    };
+    let path = torrent_dir.join(CONTROL_FILE);
+    while signals.recv().await.is_some() {
+        info!("SIGUSR1 received, reading {}", path.display());
+        process(&path).await;
+    }
+}
+
+#[cfg(not(unix))]
+pub async fn watch_signal(_torrent_dir: PathBuf) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_line() {
+        let hash = "b507c6964ffa3faaaa1aa3ac2d422d39a9c9e246";
+        assert_eq!(
+            parse_line(&format!("pause {hash}")),
+            Some(Command::Pause(Target::Torrent(
+                info_hash_from_hex(hash).unwrap()
+            )))
+        );
+        assert_eq!(parse_line("RESUME *"), Some(Command::Resume(Target::All)));
+        assert_eq!(parse_line("pause"), None);
+        assert_eq!(parse_line("pause abc"), None);
+        assert_eq!(parse_line("stop *"), None);
+        assert_eq!(parse_line("pause * extra"), None);
+    }
+}
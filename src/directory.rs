@@ -1,5 +1,7 @@
-use crate::{TORRENTS, torrent::Torrent};
-use std::path::PathBuf;
+use crate::torrent::{Torrent, is_torrent_file, load_file, paused_marker, unregistered_marker};
+use crate::{CONFIG, TORRENTS};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use tokio::sync::Mutex;
 use tracing::{error, info, warn};
 
@@ -15,42 +17,280 @@ pub async fn prepare_torrent_folder(directory: PathBuf) {
     info!("Will load torrents from: {}", directory.display());
 }
 
-/// Load torrents from the provided directory.
+/// Folder of the torrent directory where files that cannot be loaded are moved
+pub const INVALID_DIR: &str = ".invalid";
+/// Folder of the torrent directory where files of finished torrents are moved
+pub const DONE_DIR: &str = ".done";
+/// Folder of the torrent directory where files of torrents that are not registered on their trackers are moved
+pub const UNREGISTERED_DIR: &str = ".unregistered";
+
+/// Tells if the path is in a hidden folder of the torrent directory (i.e. `.invalid` or `.done`), it is not loaded
+pub fn is_hidden(directory: &Path, path: &Path) -> bool {
+    path.strip_prefix(directory).is_ok_and(|relative| {
+        relative.parent().is_some_and(|p| {
+            p.components()
+                .any(|c| c.as_os_str().to_string_lossy().starts_with('.'))
+        })
+    })
+}
+
+/// Move a file to a folder of the torrent directory, a number is added to its name if it is already used
+fn move_to(directory: &Path, folder: &str, path: &Path) -> std::io::Result<PathBuf> {
+    let target_dir = directory.join(folder);
+    std::fs::create_dir_all(&target_dir)?;
+    let file_name = path.file_name().unwrap_or(path.as_os_str());
+    let mut target = target_dir.join(file_name);
+    let mut n = 1;
+    while target.exists() {
+        // i.e. `name.1.torrent`
+        let mut name = path.file_stem().unwrap_or(file_name).to_owned();
+        name.push(format!(".{n}"));
+        if let Some(extension) = path.extension() {
+            name.push(".");
+            name.push(extension);
+        }
+        target = target_dir.join(name);
+        n += 1;
+    }
+    std::fs::rename(path, &target)?;
+    // do not keep the markers without their torrent
+    let _ = std::fs::remove_file(paused_marker(path));
+    let _ = std::fs::remove_file(unregistered_marker(path));
+    Ok(target)
+}
+
+/// Error of a torrent file whose torrents cannot be announced
+pub const NO_URL_ERROR: &str = "no supported tracker URL";
+
+/// Tells if none of the torrents of a file has a supported tracker URL (DHT only or unsupported schemes)
+pub fn without_url(torrents: &[Torrent]) -> bool {
+    !torrents.is_empty() && torrents.iter().all(|t| t.urls.is_empty())
+}
+
+/// Move a file that cannot be loaded to the `.invalid` folder, with the error in `<name>.error.txt`.
 ///
-/// Add a torrent to the list. If the filename does not end with .torrent, the file is not processed.
+/// It does nothing if `quarantine_invalid` is disabled.
+pub fn quarantine_invalid(directory: &Path, path: &Path, error: &str) {
+    if !CONFIG.get().is_some_and(|c| c.quarantine_invalid) {
+        return;
+    }
+    match quarantine(directory, INVALID_DIR, path, error) {
+        Ok(target) => warn!("Invalid torrent file moved to {}", target.display()),
+        Err(e) => error!("Cannot move invalid file {}: {e}", path.display()),
+    }
+}
+
+/// Move a file to a folder of the torrent directory and write its error next to it
+fn quarantine(
+    directory: &Path,
+    folder: &str,
+    path: &Path,
+    error: &str,
+) -> std::io::Result<PathBuf> {
+    let target = move_to(directory, folder, path)?;
+    let mut error_file = target.clone().into_os_string();
+    error_file.push(".error.txt");
+    let content = format!("{}\n{error}\n", path.display());
+    if let Err(e) = std::fs::write(&error_file, content) {
+        warn!("Cannot write {}: {e}", PathBuf::from(error_file).display());
+    }
+    Ok(target)
+}
+
+/// Move the file of a finished torrent to the `.done` folder
+pub fn move_done(directory: &Path, path: &Path) {
+    match move_to(directory, DONE_DIR, path) {
+        Ok(target) => info!("Torrent file moved to {}", target.display()),
+        Err(e) => error!("Cannot move finished file {}: {e}", path.display()),
+    }
+}
+
+/// Move the file of a torrent that is not registered on its trackers to the `.unregistered` folder, with the failure
+/// reasons of the trackers in `<name>.error.txt`
+pub fn move_unregistered(directory: &Path, path: &Path, reasons: &str) {
+    match quarantine(directory, UNREGISTERED_DIR, path, reasons) {
+        Ok(target) => info!("Unregistered torrent file moved to {}", target.display()),
+        Err(e) => error!("Cannot move unregistered file {}: {e}", path.display()),
+    }
+}
+
+/// Get the category of a torrent file: the name of its first subfolder in the torrent directory
+pub fn category_of(directory: &Path, path: &Path) -> Option<String> {
+    let relative = path.strip_prefix(directory).ok()?;
+    let mut components = relative.components();
+    let first = components.next()?;
+    // the file itself is not a category
+    components.next()?;
+    Some(first.as_os_str().to_string_lossy().into_owned())
+}
+
+/// List files of the directory and its subfolders. Symbolic links to folders and hidden folders are not followed.
+pub fn list_files(directory: &Path, files: &mut Vec<PathBuf>) {
+    let entries = match std::fs::read_dir(directory) {
+        Ok(entries) => entries,
+        Err(e) => {
+            error!("Cannot read directory {}: {e}", directory.display());
+            return;
+        }
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        match entry.file_type() {
+            Ok(t) if t.is_dir() => {
+                if !entry.file_name().to_string_lossy().starts_with('.') {
+                    list_files(&path, files);
+                }
+            }
+            Ok(_) => files.push(path),
+            Err(e) => error!("Cannot get file type of {}: {e}", path.display()),
+        }
+    }
+}
+
+/// Load torrents from the provided directory and its subfolders.
+///
+/// Add torrents to the list. Only `.torrent` files and `.magnet` files (one magnet link per line) are processed.
+/// Torrents in a subfolder get its name as category.
 pub async fn load_torrents(directory: PathBuf) -> u16 {
-    let paths = std::fs::read_dir(&directory).expect("Cannot read torrent directory");
+    let mut paths = Vec::new();
+    list_files(&directory, &mut paths);
+    paths.sort();
     let mut count = 0u16;
     let list = &mut *TORRENTS.write().await;
     let mut added_hashes: Vec<String> = Vec::new();
-    for p in paths {
-        let path = p.expect("Cannot get torrent path").path();
-        if let Some(extension) = path.clone().extension()
-            && extension.eq_ignore_ascii_case("torrent")
-        {
-            match Torrent::from_file(path.clone()) {
-                Ok(torrent) => {
-                    info!("Found torrent {}", path.display());
-                    // info!("Found torrent {} {:?}", path.display(), torrent);
+    for path in paths {
+        if !is_torrent_file(&path) {
+            continue;
+        }
+        let category = category_of(&directory, &path);
+        let paused = paused_marker(&path).exists();
+        let mut loaded = false;
+        let mut errors: Vec<String> = Vec::new();
+        for result in load_file(&path) {
+            match result {
+                Ok(mut torrent) => {
+                    info!("Found torrent {} ({})", torrent.name, path.display());
                     // TODO: dedup, ignore UDP
                     if torrent.urls.is_empty() {
                         warn!(
                             "Skipping torrent because there is no URL (DHT or not supported URLs)"
                         );
+                        errors.push(NO_URL_ERROR.to_string());
                         continue;
                     }
+                    torrent.paused = paused;
+                    torrent.category = category.clone();
+                    loaded = true;
+                    if torrent.paused {
+                        info!("Torrent is paused: {}", torrent.name);
+                    }
                     if added_hashes.contains(&torrent.info_hash_urlencoded) {
                         warn!("A torrent with the same hash is already added");
                     } else {
                         added_hashes.push(torrent.info_hash_urlencoded.clone());
-                        list.push(Mutex::new(torrent));
+                        list.push(Arc::new(Mutex::new(torrent)));
                         count += 1;
                     }
                 }
-                Err(e) => error!("Cannot add torrent {}: {e}", path.display()),
+                Err(e) => {
+                    error!("Cannot add torrent {}: {e}", path.display());
+                    errors.push(e.to_string());
+                }
             }
         }
+        if !loaded && !errors.is_empty() {
+            quarantine_invalid(&directory, &path, &errors.join("\n"));
+        }
     }
     info!("{} torrent(s) loaded", count);
     count
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_category_of() {
+        let dir = Path::new("/tmp/torrents");
+        assert_eq!(category_of(dir, Path::new("/tmp/torrents/a.torrent")), None);
+        assert_eq!(
+            category_of(dir, Path::new("/tmp/torrents/movies/a.torrent")),
+            Some(String::from("movies"))
+        );
+        assert_eq!(
+            category_of(dir, Path::new("/tmp/torrents/movies/2024/a.torrent")),
+            Some(String::from("movies"))
+        );
+        assert_eq!(category_of(dir, Path::new("/tmp/other/a.torrent")), None);
+    }
+
+    #[test]
+    fn test_is_hidden() {
+        let dir = Path::new("/tmp/torrents");
+        assert!(is_hidden(
+            dir,
+            Path::new("/tmp/torrents/.invalid/a.torrent")
+        ));
+        assert!(is_hidden(
+            dir,
+            Path::new("/tmp/torrents/movies/.done/a.torrent")
+        ));
+        assert!(!is_hidden(dir, Path::new("/tmp/torrents/movies/a.torrent")));
+        assert!(!is_hidden(dir, Path::new("/tmp/torrents/.a.torrent")));
+    }
+
+    #[test]
+    fn test_move_to() {
+        let mut dir = std::env::temp_dir();
+        dir.push("ratioup-test-quarantine");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("a.torrent");
+        std::fs::write(&path, b"invalid").unwrap();
+        std::fs::write(paused_marker(&path), b"").unwrap();
+        std::fs::write(unregistered_marker(&path), b"").unwrap();
+        let target = move_to(&dir, INVALID_DIR, &path).unwrap();
+        assert_eq!(target, dir.join(INVALID_DIR).join("a.torrent"));
+        assert!(!path.exists());
+        assert!(!paused_marker(&path).exists());
+        assert!(!unregistered_marker(&path).exists());
+
+        // the name is already used
+        std::fs::write(&path, b"invalid").unwrap();
+        let target = move_to(&dir, INVALID_DIR, &path).unwrap();
+        assert_eq!(target, dir.join(INVALID_DIR).join("a.1.torrent"));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_quarantine_without_url() {
+        let mut dir = std::env::temp_dir();
+        dir.push("ratioup-test-no-url");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("dht.torrent");
+        std::fs::copy("tests/Audio_20160422_archive.torrent", &path).unwrap();
+        let announced = Torrent::from_file(path.clone()).unwrap();
+        // i.e. only DHT or unsupported trackers
+        let mut dht = announced.clone();
+        dht.urls.clear();
+        assert!(without_url(std::slice::from_ref(&dht)));
+        assert!(!without_url(&[dht, announced]));
+        assert!(!without_url(&[]));
+
+        let target = quarantine(&dir, INVALID_DIR, &path, NO_URL_ERROR).unwrap();
+        assert_eq!(target, dir.join(INVALID_DIR).join("dht.torrent"));
+        assert!(!path.exists());
+        let error = std::fs::read_to_string(dir.join(INVALID_DIR).join("dht.torrent.error.txt"));
+        assert!(error.unwrap().ends_with("\nno supported tracker URL\n"));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_list_files() {
+        let mut files = Vec::new();
+        list_files(Path::new("tests"), &mut files);
+        assert!(files.contains(&PathBuf::from("tests/Audio_20160422_archive.torrent")));
+    }
+}
@@ -0,0 +1,167 @@
+// In-process event bus, it is used to publish live updates (i.e. to the dashboard with Server-Sent Events)
+use std::sync::LazyLock;
+
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+use crate::torrent::Torrent;
+use crate::utils::to_hex;
+
+/// Number of events kept for slow subscribers before they miss some
+const CAPACITY: usize = 256;
+
+static BUS: LazyLock<broadcast::Sender<BusEvent>> =
+    LazyLock::new(|| broadcast::channel(CAPACITY).0);
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BusEvent {
+    AnnounceStarted {
+        info_hash: String,
+        name: String,
+        /// `started`, `stopped` or `None` for regular announces
+        event: Option<&'static str>,
+    },
+    AnnounceCompleted {
+        info_hash: String,
+        name: String,
+        uploaded: u64,
+        seeders: u16,
+        leechers: u16,
+        interval: u64,
+        next_upload_speed: u32,
+    },
+    TrackerError {
+        info_hash: String,
+        name: String,
+        host: String,
+        /// See `AnnounceError::kind`
+        kind: &'static str,
+        message: String,
+        /// Errors of the torrent, including this one
+        error_count: u16,
+        /// First failure of the tracker since its last successful announce
+        first: bool,
+        /// The failure reason tells that the torrent is not registered
+        unregistered: bool,
+    },
+    TorrentAdded {
+        info_hash: String,
+        name: String,
+    },
+    TorrentRemoved {
+        info_hash: String,
+        name: String,
+        uploaded: u64,
+    },
+    TorrentPaused {
+        info_hash: String,
+        name: String,
+    },
+    TorrentResumed {
+        info_hash: String,
+        name: String,
+    },
+    /// A tracker told that the torrent is not registered
+    TorrentUnregistered {
+        info_hash: String,
+        name: String,
+        host: String,
+        message: String,
+    },
+    StopRuleReached {
+        info_hash: String,
+        name: String,
+        uploaded: u64,
+    },
+    RatesChanged {
+        min_upload_rate: u32,
+        max_upload_rate: u32,
+    },
+}
+
+impl BusEvent {
+    /// Name of the event, it is the `type` field of the JSON and the SSE event name
+    pub fn name(&self) -> &'static str {
+        match self {
+            BusEvent::AnnounceStarted { .. } => "announce_started",
+            BusEvent::AnnounceCompleted { .. } => "announce_completed",
+            BusEvent::TrackerError { .. } => "tracker_error",
+            BusEvent::TorrentAdded { .. } => "torrent_added",
+            BusEvent::TorrentRemoved { .. } => "torrent_removed",
+            BusEvent::TorrentPaused { .. } => "torrent_paused",
+            BusEvent::TorrentResumed { .. } => "torrent_resumed",
+            BusEvent::TorrentUnregistered { .. } => "torrent_unregistered",
+            BusEvent::StopRuleReached { .. } => "stop_rule_reached",
+            BusEvent::RatesChanged { .. } => "rates_changed",
+        }
+    }
+
+    pub fn announce_completed(t: &Torrent) -> Self {
+        BusEvent::AnnounceCompleted {
+            info_hash: to_hex(&t.info_hash),
+            name: t.name.clone(),
+            uploaded: t.uploaded,
+            seeders: t.seeders,
+            leechers: t.leechers,
+            interval: t.interval,
+            next_upload_speed: t.next_upload_speed,
+        }
+    }
+
+    pub fn torrent_added(t: &Torrent) -> Self {
+        BusEvent::TorrentAdded {
+            info_hash: to_hex(&t.info_hash),
+            name: t.name.clone(),
+        }
+    }
+
+    pub fn torrent_removed(t: &Torrent) -> Self {
+        BusEvent::TorrentRemoved {
+            info_hash: to_hex(&t.info_hash),
+            name: t.name.clone(),
+            uploaded: t.uploaded,
+        }
+    }
+}
+
+/// Publish an event to all subscribers, it is dropped if there is none
+pub fn publish(event: BusEvent) {
+    let _ = BUS.send(event);
+}
+
+pub fn subscribe() -> broadcast::Receiver<BusEvent> {
+    BUS.subscribe()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_publish_subscribe() {
+        // no subscriber, must not fail
+        publish(BusEvent::RatesChanged {
+            min_upload_rate: 1,
+            max_upload_rate: 2,
+        });
+        let mut rx = subscribe();
+        let event = BusEvent::RatesChanged {
+            min_upload_rate: 1024,
+            max_upload_rate: 2048,
+        };
+        publish(event.clone());
+        // other tests may publish events at the same time
+        loop {
+            let received = rx.recv().await.unwrap();
+            if received == event {
+                break;
+            }
+        }
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            "{\"type\":\"rates_changed\",\"min_upload_rate\":1024,\"max_upload_rate\":2048}"
+        );
+        assert_eq!(event.name(), "rates_changed");
+    }
+}
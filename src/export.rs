@@ -0,0 +1,227 @@
+// Formats of the stats files (`output_stats`), they are all rendered from the same `Stats` snapshot
+// - JSON, used by the dashboard
+// - CSV, one row per torrent
+// - Prometheus text format, for the textfile collector of node_exporter
+// - HTML report: the dashboard with the stats inlined, it opens from `file://`
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+
+use crate::json_output::Stats;
+use crate::metrics::{write_header, write_torrent_metrics};
+
+/// Dashboard, it loads `RatioUp.json` unless the stats are inlined
+pub const INDEX_HTML: &str = include_str!("../www/index.html");
+
+/// Format of a stats file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    Csv,
+    Prometheus,
+    Html,
+}
+
+impl Format {
+    /// Format from its name in the config: `json`, `csv`, `prometheus` or `html`
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "json" => Some(Format::Json),
+            "csv" => Some(Format::Csv),
+            "prometheus" | "prom" => Some(Format::Prometheus),
+            "html" => Some(Format::Html),
+            _ => None,
+        }
+    }
+
+    /// Format from the extension of a file: `.csv`, `.prom`, `.html`/`.htm`, JSON otherwise
+    pub fn from_path(path: &Path) -> Self {
+        match path
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_ascii_lowercase())
+            .as_deref()
+        {
+            Some("csv") => Format::Csv,
+            Some("prom") => Format::Prometheus,
+            Some("html" | "htm") => Format::Html,
+            _ => Format::Json,
+        }
+    }
+}
+
+/// A stats file of `output_stats`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatsOutput {
+    pub path: PathBuf,
+    pub format: Format,
+}
+
+/// Render the stats in the given format
+pub fn render(stats: &Stats, format: Format, hide_names: bool) -> String {
+    match format {
+        Format::Json => serde_json::to_string(stats).unwrap_or_default(),
+        Format::Csv => to_csv(stats),
+        Format::Prometheus => to_prometheus(stats, hide_names),
+        Format::Html => to_html(stats),
+    }
+}
+
+/// Quote a CSV field if needed (RFC 4180)
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn format_time(time: Option<DateTime<Utc>>) -> String {
+    time.map(|t| t.to_rfc3339()).unwrap_or_default()
+}
+
+fn to_csv(stats: &Stats) -> String {
+    let mut out = String::with_capacity(256 * (stats.torrents.len() + 1));
+    out.push_str(
+        "name,info_hash,category,length,private,paused,uploaded,seeders,leechers,next_upload_speed,\
+        last_announce,next_announce,error_count,last_error,urls\r\n",
+    );
+    for t in &stats.torrents {
+        let fields = [
+            csv_field(&t.name),
+            t.info_hash.clone(),
+            csv_field(t.category.as_deref().unwrap_or_default()),
+            t.length.to_string(),
+            t.private.to_string(),
+            t.paused.to_string(),
+            t.uploaded.to_string(),
+            t.seeders.to_string(),
+            t.leechers.to_string(),
+            t.next_upload_speed.to_string(),
+            format_time(t.last_announce),
+            format_time(t.next_announce),
+            t.error_count.to_string(),
+            csv_field(t.last_error.as_deref().unwrap_or_default()),
+            // URLs cannot contain spaces
+            csv_field(&t.urls.join(" ")),
+        ];
+        out.push_str(&fields.join(","));
+        out.push_str("\r\n");
+    }
+    out
+}
+
+/// Global metrics of the stats then the per torrent metrics of `/metrics`
+fn to_prometheus(stats: &Stats, hide_names: bool) -> String {
+    let mut out = String::with_capacity(4096);
+    write_header(
+        &mut out,
+        "ratioup_started_timestamp_seconds",
+        "gauge",
+        "Start time of RatioUp since the Unix epoch",
+    );
+    let _ = writeln!(
+        out,
+        "ratioup_started_timestamp_seconds {}",
+        stats.started.timestamp()
+    );
+    write_header(
+        &mut out,
+        "ratioup_uploaded_bytes_total",
+        "counter",
+        "Uploaded bytes of all torrents reported to trackers since the start",
+    );
+    let _ = writeln!(out, "ratioup_uploaded_bytes_total {}", stats.total_uploaded);
+    write_torrent_metrics(&mut out, &stats.torrents, hide_names);
+    out
+}
+
+/// Dashboard with the stats inlined, `window.RATIOUP_STATS` is used instead of fetching `RatioUp.json`
+fn to_html(stats: &Stats) -> String {
+    // `<` is escaped so a name cannot close the script element
+    let json = serde_json::to_string(stats)
+        .unwrap_or_default()
+        .replace('<', "\\u003c");
+    let script = format!("<script>window.RATIOUP_STATS = {json};</script>\n</head>");
+    INDEX_HTML.replacen("</head>", &script, 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::json_output::{SCHEMA_VERSION, TorrentStats};
+    use crate::torrent::Torrent;
+
+    fn stats() -> Stats {
+        let mut t = Torrent::from_magnet(
+            "magnet:?xt=urn:btih:0123456789abcdef0123456789abcdef01234567&dn=a,%22b%22%3C/script%3E\
+            &tr=http://tracker.tld/announce&tr=udp://tracker.tld:6969",
+        )
+        .unwrap();
+        t.uploaded = 42;
+        t.seeders = 3;
+        let torrent = TorrentStats::new(&t, false);
+        Stats {
+            schema_version: SCHEMA_VERSION,
+            started: DateTime::from_timestamp(1700000000, 0).unwrap(),
+            client: String::from("Transmission 3.00"),
+            min_upload_rate: 1,
+            max_upload_rate: 2,
+            total_uploaded: 42,
+            torrents: vec![torrent],
+            history: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_format() {
+        assert_eq!(Format::from_path(Path::new("a/stats.CSV")), Format::Csv);
+        assert_eq!(
+            Format::from_path(Path::new("stats.prom")),
+            Format::Prometheus
+        );
+        assert_eq!(Format::from_path(Path::new("report.html")), Format::Html);
+        assert_eq!(Format::from_path(Path::new("RatioUp.json")), Format::Json);
+        assert_eq!(Format::from_path(Path::new("stats")), Format::Json);
+        assert_eq!(Format::from_name("Prometheus"), Some(Format::Prometheus));
+        assert_eq!(Format::from_name("xml"), None);
+    }
+
+    #[test]
+    fn test_csv() {
+        let csv = render(&stats(), Format::Csv, false);
+        let lines: Vec<&str> = csv.split("\r\n").collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("name,info_hash,"));
+        assert_eq!(
+            lines[1],
+            "\"a,\"\"b\"\"</script>\",0123456789abcdef0123456789abcdef01234567,,0,false,false,42,3,0,0,,,0,,\
+            http://tracker.tld/announce udp://tracker.tld:6969"
+        );
+    }
+
+    #[test]
+    fn test_prometheus() {
+        let text = render(&stats(), Format::Prometheus, true);
+        assert!(text.contains("ratioup_started_timestamp_seconds 1700000000\n"));
+        assert!(text.contains(
+            "ratioup_torrent_uploaded_bytes_total{info_hash=\"0123456789abcdef0123456789abcdef01234567\"} 42\n"
+        ));
+        // not announced yet
+        assert!(!text.contains("ratioup_torrent_last_announce_timestamp_seconds{"));
+        assert!(!text.contains("name="));
+        assert!(
+            render(&stats(), Format::Prometheus, false).contains("name=\"a,\\\"b\\\"</script>\"")
+        );
+    }
+
+    #[test]
+    fn test_html() {
+        let html = render(&stats(), Format::Html, false);
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains("window.RATIOUP_STATS = {\"schema_version\":"));
+        // the name cannot close the script element
+        assert!(html.contains("\\\"b\\\"\\u003c/script>"));
+        assert!(!html.contains("\"b\"</script>"));
+    }
+}
@@ -0,0 +1,339 @@
+// History of the announces: uploaded bytes, upload rate, seeders and leechers reported to each tracker for each
+// torrent. Announces are kept for 2 days, then only their sums by hour (14 days) and by day (2 years), so the file stays
+// small. It is saved in `history_file` and the daily upload by tracker is in the stats for the dashboard.
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::{error, info};
+
+use crate::json_output::write_atomic;
+
+const HOUR: i64 = 3600;
+const DAY: i64 = 24 * HOUR;
+/// How long announces, hourly and daily sums are kept (in seconds)
+const RAW_RETENTION: i64 = 2 * DAY;
+const HOURLY_RETENTION: i64 = 14 * DAY;
+const DAILY_RETENTION: i64 = 731 * DAY;
+/// The history file is saved at most this often, and on exit
+const SAVE_INTERVAL: Duration = Duration::from_secs(600);
+/// Days of daily upload in the stats
+pub const STATS_DAYS: usize = 30;
+
+/// An announce, or the sum of the announces of an hour or a day. It is written as an array to keep the file small:
+/// `[time, uploaded, rate, seeders, leechers, announces]`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "PointTuple", into = "PointTuple")]
+pub struct Point {
+    /// Unix timestamp of the announce, or of the start of the hour or the day
+    pub time: i64,
+    /// Uploaded bytes reported to the tracker
+    pub uploaded: u64,
+    /// Average upload rate in bytes per second
+    pub rate: u32,
+    /// Average number of seeders and leechers returned by the tracker
+    pub seeders: u32,
+    pub leechers: u32,
+    pub announces: u32,
+}
+
+type PointTuple = (i64, u64, u32, u32, u32, u32);
+
+impl From<PointTuple> for Point {
+    fn from((time, uploaded, rate, seeders, leechers, announces): PointTuple) -> Self {
+        Point {
+            time,
+            uploaded,
+            rate,
+            seeders,
+            leechers,
+            announces,
+        }
+    }
+}
+
+impl From<Point> for PointTuple {
+    fn from(p: Point) -> Self {
+        (
+            p.time,
+            p.uploaded,
+            p.rate,
+            p.seeders,
+            p.leechers,
+            p.announces,
+        )
+    }
+}
+
+/// Weighted average of two averages
+fn merge_average(a: u32, a_count: u32, b: u32, b_count: u32) -> u32 {
+    let count = a_count as u64 + b_count as u64;
+    if count == 0 {
+        return 0;
+    }
+    ((a as u64 * a_count as u64 + b as u64 * b_count as u64) / count) as u32
+}
+
+impl Point {
+    /// Add a point to the sum of a period
+    fn add(&mut self, other: &Point) {
+        self.uploaded += other.uploaded;
+        self.rate = merge_average(self.rate, self.announces, other.rate, other.announces);
+        self.seeders = merge_average(self.seeders, self.announces, other.seeders, other.announces);
+        self.leechers = merge_average(
+            self.leechers,
+            self.announces,
+            other.leechers,
+            other.announces,
+        );
+        self.announces += other.announces;
+    }
+}
+
+/// Add a point to the sum of its period, the points are sorted by time
+fn add_to_period(points: &mut Vec<Point>, point: &Point, period: i64) {
+    let start = point.time - point.time.rem_euclid(period);
+    match points.last_mut() {
+        Some(last) if last.time == start => last.add(point),
+        _ => points.push(Point {
+            time: start,
+            ..*point
+        }),
+    }
+}
+
+/// Announces of a torrent to a tracker
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Series {
+    pub info_hash: String,
+    /// Host of the tracker
+    pub tracker: String,
+    pub announces: Vec<Point>,
+    pub hourly: Vec<Point>,
+    pub daily: Vec<Point>,
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct History {
+    pub series: Vec<Series>,
+}
+
+impl History {
+    /// Record an announce, the hourly and daily sums are updated
+    pub fn record(&mut self, info_hash: &str, tracker: &str, point: Point) {
+        let index = match self
+            .series
+            .iter()
+            .position(|s| s.info_hash == info_hash && s.tracker == tracker)
+        {
+            Some(index) => index,
+            None => {
+                self.series.push(Series {
+                    info_hash: info_hash.to_string(),
+                    tracker: tracker.to_string(),
+                    ..Default::default()
+                });
+                self.series.len() - 1
+            }
+        };
+        let series = &mut self.series[index];
+        series.announces.push(point);
+        add_to_period(&mut series.hourly, &point, HOUR);
+        add_to_period(&mut series.daily, &point, DAY);
+    }
+
+    /// Remove the points older than their retention, and the series without points
+    pub fn prune(&mut self, now: i64) {
+        for series in &mut self.series {
+            series.announces.retain(|p| p.time >= now - RAW_RETENTION);
+            series.hourly.retain(|p| p.time >= now - HOURLY_RETENTION);
+            series.daily.retain(|p| p.time >= now - DAILY_RETENTION);
+        }
+        self.series.retain(|s| !s.daily.is_empty());
+    }
+
+    /// Uploaded bytes by tracker for each of the last `days` days, the last one is today
+    pub fn daily_upload_by_tracker(&self, now: i64, days: usize) -> Vec<TrackerHistory> {
+        let today = now - now.rem_euclid(DAY);
+        let first = today - (days as i64 - 1) * DAY;
+        let mut trackers: BTreeMap<&str, Vec<u64>> = BTreeMap::new();
+        for series in &self.series {
+            let uploaded = trackers
+                .entry(&series.tracker)
+                .or_insert_with(|| vec![0; days]);
+            for point in series.daily.iter().filter(|p| p.time >= first) {
+                if let Some(day) = uploaded.get_mut(((point.time - first) / DAY) as usize) {
+                    *day += point.uploaded;
+                }
+            }
+        }
+        trackers
+            .into_iter()
+            .map(|(tracker, uploaded)| TrackerHistory {
+                tracker: tracker.to_string(),
+                first_day: DateTime::from_timestamp(first, 0).unwrap_or_default(),
+                daily_uploaded: uploaded,
+            })
+            .collect()
+    }
+}
+
+/// Daily upload to a tracker, in the stats
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TrackerHistory {
+    pub tracker: String,
+    /// Start of the first day of `daily_uploaded`
+    pub first_day: DateTime<Utc>,
+    /// Uploaded bytes by day, the last one is today
+    pub daily_uploaded: Vec<u64>,
+}
+
+static HISTORY: Mutex<History> = Mutex::new(History { series: Vec::new() });
+static LAST_SAVE: Mutex<Option<Instant>> = Mutex::new(None);
+
+/// Record an announce to a tracker
+pub fn record(
+    info_hash: &str,
+    tracker: &str,
+    uploaded: u64,
+    rate: u32,
+    seeders: u16,
+    leechers: u16,
+) {
+    let point = Point {
+        time: Utc::now().timestamp(),
+        uploaded,
+        rate,
+        seeders: seeders.into(),
+        leechers: leechers.into(),
+        announces: 1,
+    };
+    HISTORY.lock().unwrap().record(info_hash, tracker, point);
+}
+
+/// Daily upload by tracker of the last [`STATS_DAYS`] days
+pub fn daily_upload_by_tracker() -> Vec<TrackerHistory> {
+    HISTORY
+        .lock()
+        .unwrap()
+        .daily_upload_by_tracker(Utc::now().timestamp(), STATS_DAYS)
+}
+
+/// Remove the points older than their retention, with or without `history_file`
+pub fn prune() {
+    HISTORY.lock().unwrap().prune(Utc::now().timestamp());
+}
+
+/// Load the history file, if it exists
+pub async fn load(path: &Path) {
+    let data = match tokio::fs::read(path).await {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return,
+        Err(e) => {
+            error!("Cannot read history file {}: {e}", path.display());
+            return;
+        }
+    };
+    match serde_json::from_slice::<History>(&data) {
+        Ok(mut history) => {
+            history.prune(Utc::now().timestamp());
+            info!("History loaded: {} series", history.series.len());
+            *HISTORY.lock().unwrap() = history;
+        }
+        Err(e) => error!("Invalid history file {}: {e}", path.display()),
+    }
+}
+
+/// Save the history to `history_file` if it was not saved for a while, or always with `force` (i.e. on exit)
+pub async fn save(force: bool) {
+    let Some(path) = crate::CONFIG.get().and_then(|c| c.history_file.as_ref()) else {
+        return;
+    };
+    {
+        let mut last_save = LAST_SAVE.lock().unwrap();
+        if !force && last_save.is_some_and(|time| time.elapsed() < SAVE_INTERVAL) {
+            return;
+        }
+        *last_save = Some(Instant::now());
+    }
+    let data = serde_json::to_vec(&*HISTORY.lock().unwrap()).unwrap_or_default();
+    if let Err(e) = write_atomic(path, &data).await {
+        error!("Cannot write history file {}: {e}", path.display());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(time: i64, uploaded: u64, seeders: u32) -> Point {
+        Point {
+            time,
+            uploaded,
+            rate: 100,
+            seeders,
+            leechers: 2,
+            announces: 1,
+        }
+    }
+
+    #[test]
+    fn test_record() {
+        let mut history = History::default();
+        let start = 1_700_000_000 - 1_700_000_000 % DAY;
+        history.record("aa", "tracker.tld", point(start + 10, 1000, 4));
+        history.record("aa", "tracker.tld", point(start + 1800, 2000, 8));
+        history.record("aa", "tracker.tld", point(start + HOUR + 10, 500, 6));
+        history.record("aa", "other.tld", point(start + DAY + 10, 300, 1));
+        assert_eq!(history.series.len(), 2);
+
+        let series = &history.series[0];
+        assert_eq!(series.announces.len(), 3);
+        assert_eq!(
+            series.hourly,
+            vec![
+                Point {
+                    time: start,
+                    uploaded: 3000,
+                    rate: 100,
+                    seeders: 6,
+                    leechers: 2,
+                    announces: 2
+                },
+                Point {
+                    time: start + HOUR,
+                    ..point(start + HOUR + 10, 500, 6)
+                }
+            ]
+        );
+        assert_eq!(series.daily.len(), 1);
+        assert_eq!(series.daily[0].uploaded, 3500);
+        assert_eq!(series.daily[0].announces, 3);
+
+        // compact file
+        let json = serde_json::to_string(&history.series[1].daily).unwrap();
+        assert_eq!(json, format!("[[{},300,100,1,2,1]]", start + DAY));
+        let loaded: History =
+            serde_json::from_str(&serde_json::to_string(&history).unwrap()).unwrap();
+        assert_eq!(loaded, history);
+
+        let trackers = history.daily_upload_by_tracker(start + DAY + 20, 3);
+        assert_eq!(trackers[0].tracker, "other.tld");
+        assert_eq!(trackers[0].daily_uploaded, vec![0, 0, 300]);
+        assert_eq!(trackers[1].daily_uploaded, vec![0, 3500, 0]);
+        assert_eq!(trackers[1].first_day.timestamp(), start - DAY);
+
+        // announces are removed after 2 days, hourly sums after 14 days
+        history.prune(start + 3 * DAY);
+        assert!(history.series[0].announces.is_empty());
+        assert_eq!(history.series[0].hourly.len(), 2);
+        history.prune(start + 20 * DAY);
+        assert!(history.series[0].hourly.is_empty());
+        assert_eq!(history.series[0].daily.len(), 1);
+        history.prune(start + 800 * DAY);
+        assert!(history.series.is_empty());
+    }
+}
@@ -0,0 +1,227 @@
+// Shell commands run on the lifecycle events (`[hooks]`), for integrations that are not built into RatioUp. The context
+// is passed in `RATIOUP_*` environment variables. They run in the background with a timeout, except the shutdown hook
+// that is waited for before exiting.
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::process::Command;
+use tokio::sync::Semaphore;
+use tokio::sync::broadcast::Receiver;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{debug, error, warn};
+
+use crate::events::BusEvent;
+
+/// Hooks running at the same time, the other ones wait for their turn
+const MAX_RUNNING: usize = 4;
+
+/// Events that can have a hook
+pub const EVENTS: [&str; 5] = [
+    "torrent_added",
+    "torrent_removed",
+    "announce_failed",
+    "stop_rule",
+    "shutdown",
+];
+
+/// Hook event and its environment variables for an event of the bus, if it can have a hook
+fn from_event(event: &BusEvent) -> Option<(&'static str, Vec<(&'static str, String)>)> {
+    Some(match event {
+        BusEvent::TorrentAdded { info_hash, name } => (
+            "torrent_added",
+            vec![
+                ("RATIOUP_NAME", name.clone()),
+                ("RATIOUP_INFO_HASH", info_hash.clone()),
+            ],
+        ),
+        BusEvent::TorrentRemoved {
+            info_hash,
+            name,
+            uploaded,
+        } => (
+            "torrent_removed",
+            vec![
+                ("RATIOUP_NAME", name.clone()),
+                ("RATIOUP_INFO_HASH", info_hash.clone()),
+                ("RATIOUP_UPLOADED", uploaded.to_string()),
+            ],
+        ),
+        BusEvent::StopRuleReached {
+            info_hash,
+            name,
+            uploaded,
+        } => (
+            "stop_rule",
+            vec![
+                ("RATIOUP_NAME", name.clone()),
+                ("RATIOUP_INFO_HASH", info_hash.clone()),
+                ("RATIOUP_UPLOADED", uploaded.to_string()),
+            ],
+        ),
+        BusEvent::TrackerError {
+            info_hash,
+            name,
+            host,
+            message,
+            ..
+        } => (
+            "announce_failed",
+            vec![
+                ("RATIOUP_NAME", name.clone()),
+                ("RATIOUP_INFO_HASH", info_hash.clone()),
+                ("RATIOUP_TRACKER", host.clone()),
+                ("RATIOUP_ERROR", message.clone()),
+            ],
+        ),
+        _ => return None,
+    })
+}
+
+/// Run a command with the shell and wait for it, it is killed after the timeout
+async fn execute(
+    command: &str,
+    event: &str,
+    env: &[(&str, String)],
+    timeout: Duration,
+) -> Result<(), String> {
+    #[cfg(windows)]
+    let mut cmd = {
+        let mut cmd = Command::new("cmd");
+        cmd.arg("/C");
+        cmd
+    };
+    #[cfg(not(windows))]
+    let mut cmd = {
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c");
+        cmd
+    };
+    cmd.arg(command)
+        .env("RATIOUP_EVENT", event)
+        .envs(env.iter().map(|(key, value)| (key, value)))
+        .stdin(Stdio::null())
+        .kill_on_drop(true);
+    let mut child = cmd.spawn().map_err(|e| e.to_string())?;
+    match tokio::time::timeout(timeout, child.wait()).await {
+        Ok(Ok(status)) if status.success() => Ok(()),
+        Ok(Ok(status)) => Err(format!("exited with {status}")),
+        Ok(Err(e)) => Err(e.to_string()),
+        Err(_) => Err(format!("killed after {timeout:?}")),
+    }
+}
+
+/// Run the hook of an event, if there is one
+async fn run_hook(event: &'static str, env: Vec<(&'static str, String)>) {
+    let Some(config) = crate::CONFIG.get() else {
+        return;
+    };
+    let Some(command) = config.hooks.get(event) else {
+        return;
+    };
+    debug!("Running {event} hook");
+    if let Err(e) = execute(
+        command,
+        event,
+        &env,
+        Duration::from_secs(config.hook_timeout),
+    )
+    .await
+    {
+        warn!("{event} hook failed: {e}");
+    }
+}
+
+/// Run the hooks of the events received on the bus, in the background so they never block the scheduler. At most
+/// [`MAX_RUNNING`] commands run at the same time, i.e. when many torrents are added at once.
+pub async fn run(mut rx: Receiver<BusEvent>) {
+    if crate::CONFIG.get().is_none_or(|c| c.hooks.is_empty()) {
+        return;
+    }
+    let running = Arc::new(Semaphore::new(MAX_RUNNING));
+    loop {
+        match rx.recv().await {
+            Ok(event) => {
+                if let Some((event, env)) = from_event(&event) {
+                    let running = Arc::clone(&running);
+                    tokio::spawn(async move {
+                        // the semaphore is never closed
+                        let Ok(_permit) = running.acquire().await else {
+                            return;
+                        };
+                        run_hook(event, env).await;
+                    });
+                }
+            }
+            Err(RecvError::Lagged(count)) => error!("Hooks missed {count} event(s)"),
+            Err(RecvError::Closed) => break,
+        }
+    }
+}
+
+/// Run the shutdown hook and wait for it, with the total uploaded bytes
+pub async fn shutdown(uploaded: u64) {
+    run_hook("shutdown", vec![("RATIOUP_UPLOADED", uploaded.to_string())]).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_event() {
+        let (event, env) = from_event(&BusEvent::StopRuleReached {
+            info_hash: String::from("aa"),
+            name: String::from("linux.iso"),
+            uploaded: 42,
+        })
+        .unwrap();
+        assert_eq!(event, "stop_rule");
+        assert!(env.contains(&("RATIOUP_UPLOADED", String::from("42"))));
+        let (event, env) = from_event(&BusEvent::TrackerError {
+            info_hash: String::from("aa"),
+            name: String::from("linux.iso"),
+            host: String::from("tracker.tld"),
+            kind: "tracker",
+            message: String::from("tracker error: unregistered torrent"),
+            error_count: 1,
+            first: true,
+            unregistered: true,
+        })
+        .unwrap();
+        assert_eq!(event, "announce_failed");
+        assert!(env.contains(&("RATIOUP_TRACKER", String::from("tracker.tld"))));
+        assert!(
+            from_event(&BusEvent::RatesChanged {
+                min_upload_rate: 1,
+                max_upload_rate: 2
+            })
+            .is_none()
+        );
+    }
+
+    #[cfg(not(windows))]
+    #[tokio::test]
+    async fn test_execute() {
+        let path = std::env::temp_dir().join(format!("ratioup-hook-{}", std::process::id()));
+        let command = format!(
+            "printf '%s %s' \"$RATIOUP_EVENT\" \"$RATIOUP_NAME\" > {}",
+            path.display()
+        );
+        let env = [("RATIOUP_NAME", String::from("a b"))];
+        execute(&command, "torrent_added", &env, Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "torrent_added a b");
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            execute("exit 3", "shutdown", &[], Duration::from_secs(5)).await,
+            Err(String::from("exited with exit status: 3"))
+        );
+        assert_eq!(
+            execute("sleep 5", "shutdown", &[], Duration::from_millis(100)).await,
+            Err(String::from("killed after 100ms"))
+        );
+    }
+}
@@ -1,78 +1,281 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
 use std::{fs, path::Path};
 
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tokio::sync::Mutex;
+
+use crate::announcer::tracker::TrackerStatus;
+use crate::export;
+use crate::history::{self, TrackerHistory};
+use crate::metadata::Metadata;
+use crate::torrent::Torrent;
+use crate::utils::to_hex;
 use crate::{STARTED, TORRENTS};
 use tracing::error;
 
-/// Check if the given output file is writable.
-pub fn writable(path: &str) -> bool {
-    if path.ends_with('/') {
-        error!("OUTPUT is a path, not a file");
-        return false;
+/// Version of the stats format, increased when a field is renamed or removed
+pub const SCHEMA_VERSION: u32 = 2;
+
+/// JSON stats of RatioUp, for the dashboard
+#[derive(Debug, Serialize)]
+pub struct Stats {
+    pub schema_version: u32,
+    pub started: DateTime<Utc>,
+    /// Name of the fake client
+    pub client: String,
+    pub min_upload_rate: u32,
+    pub max_upload_rate: u32,
+    pub torrents: Vec<TorrentStats>,
+    pub total_uploaded: u64,
+    /// Daily upload by tracker of the last days
+    pub history: Vec<TrackerHistory>,
+}
+
+/// Stats of a torrent
+#[derive(Debug, Serialize)]
+pub struct TorrentStats {
+    pub name: String,
+    pub info_hash: String,
+    pub info_hash_v2: Option<String>,
+    pub length: u64,
+    pub private: bool,
+    pub paused: bool,
+    pub category: Option<String>,
+    pub uploaded: u64,
+    pub seeders: u16,
+    pub leechers: u16,
+    pub next_upload_speed: u32,
+    pub urls: Vec<String>,
+    /// Last announce to one of the trackers, `None` until the torrent is announced
+    pub last_announce: Option<DateTime<Utc>>,
+    /// Expected time of the next announce, `None` when paused or not announced yet
+    pub next_announce: Option<DateTime<Utc>>,
+    pub error_count: u16,
+    pub last_error: Option<String>,
+    pub trackers: Vec<TrackerStats>,
+    /// Only with `stats_metadata = true`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<Metadata>,
+}
+
+/// Status of a tracker of a torrent, `last_announce` is `None` until it is announced
+#[derive(Debug, Serialize)]
+pub struct TrackerStats {
+    pub url: String,
+    #[serde(flatten)]
+    pub status: TrackerStatus,
+}
+
+impl TorrentStats {
+    pub fn new(t: &Torrent, with_metadata: bool) -> Self {
+        // the times of the trackers do not change until the next announce, unlike the elapsed time since the last one
+        let last_announce = t.trackers.values().filter_map(|s| s.last_announce).max();
+        TorrentStats {
+            name: t.name.clone(),
+            info_hash: to_hex(&t.info_hash),
+            info_hash_v2: t.info_hash_v2.as_ref().map(|hash| to_hex(hash)),
+            length: t.length,
+            private: t.private,
+            paused: t.paused,
+            category: t.category.clone(),
+            uploaded: t.uploaded,
+            seeders: t.seeders,
+            leechers: t.leechers,
+            next_upload_speed: t.next_upload_speed,
+            urls: t.urls.clone(),
+            last_announce,
+            next_announce: last_announce
+                .filter(|_| !t.paused)
+                .map(|time| time + Duration::from_secs(t.interval)),
+            error_count: t.error_count,
+            last_error: t.last_error.clone(),
+            trackers: t
+                .urls
+                .iter()
+                .map(|url| TrackerStats {
+                    url: url.clone(),
+                    status: t.trackers.get(url).cloned().unwrap_or_default(),
+                })
+                .collect(),
+            metadata: t.metadata.as_deref().filter(|_| with_metadata).cloned(),
+        }
+    }
+}
+
+/// Content and time of the last write of each stats file
+static LAST_WRITES: Mutex<BTreeMap<PathBuf, (String, Instant)>> = Mutex::const_new(BTreeMap::new());
+
+/// Check if the given output file is writable, the error explains why it is not
+pub fn writable(path: &Path) -> Result<(), String> {
+    if path.as_os_str().to_string_lossy().ends_with('/') || path.is_dir() {
+        return Err(format!("{} is a directory, not a file", path.display()));
     }
-    let p = Path::new(path);
-    let parent = p.parent().unwrap();
+    let parent = match path.parent() {
+        Some(parent) if parent.as_os_str().is_empty() => Path::new("."),
+        Some(parent) => parent,
+        None => return Err(format!("{} has no parent directory", path.display())),
+    };
     if !parent.is_dir() {
-        error!("Directory {:?} does not exist", parent.to_str());
-        return false;
+        return Err(format!("directory {} does not exist", parent.display()));
+    }
+    match fs::metadata(parent) {
+        Ok(md) if md.permissions().readonly() => {
+            Err(format!("directory {} is read-only", parent.display()))
+        }
+        Ok(_) => match fs::metadata(path) {
+            Ok(md) if md.permissions().readonly() => {
+                Err(format!("{} is read-only", path.display()))
+            }
+            _ => Ok(()),
+        },
+        Err(e) => Err(format!("cannot access {}: {e}", parent.display())),
     }
-    let md = fs::metadata(parent).unwrap();
-    !md.permissions().readonly()
 }
 
-/// Write a session file with torrent and its stats
-pub async fn write() {
-    let config = crate::CONFIG.get().unwrap();
-    if let Some(path) = config.output_stats.clone() {
-        let mut data = String::with_capacity(4096);
-
-        // fill data in struct
-        let started = *STARTED.get().unwrap();
-        data.push_str("{\"started\":\"");
-        data.push_str(&started.to_rfc3339());
-
-        // Add client info
-        data.push_str("\",\"client\":\"");
-        if let Some(client) = &*crate::CLIENT.read().await {
-            data.push_str(&client.name);
+/// Temporary file next to the stats file, it is renamed once written so readers never see a partial file
+fn temp_path(path: &Path) -> std::path::PathBuf {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    path.with_file_name(format!(".{name}.tmp"))
+}
+
+/// Write the data to a temporary file then rename it to the path
+pub async fn write_atomic(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    let temp = temp_path(path);
+    if let Err(e) = tokio::fs::write(&temp, data).await {
+        let _ = tokio::fs::remove_file(&temp).await;
+        return Err(e);
+    }
+    tokio::fs::rename(&temp, path).await
+}
+
+/// Build the stats with the torrent list
+pub async fn stats() -> Stats {
+    let with_metadata = crate::CONFIG.get().is_some_and(|c| c.stats_metadata);
+    let rates = *crate::RATES.read().await;
+    let torrents = {
+        let list = TORRENTS.read().await;
+        let mut torrents = Vec::with_capacity(list.len());
+        for m in list.iter() {
+            torrents.push(TorrentStats::new(&*m.lock().await, with_metadata));
         }
+        torrents
+    };
+    Stats {
+        schema_version: SCHEMA_VERSION,
+        started: *STARTED.get().unwrap(),
+        client: crate::CLIENT
+            .read()
+            .await
+            .as_ref()
+            .map(|client| client.name.clone())
+            .unwrap_or_default(),
+        min_upload_rate: rates.min_upload_rate,
+        max_upload_rate: rates.max_upload_rate,
+        total_uploaded: torrents.iter().map(|t| t.uploaded).sum(),
+        torrents,
+        history: history::daily_upload_by_tracker(),
+    }
+}
 
-        // Add bandwidth info
-        data.push_str("\",\"min_upload_rate\":");
-        data.push_str(&config.min_upload_rate.to_string());
-        data.push_str(",\"max_upload_rate\":");
-        data.push_str(&config.max_upload_rate.to_string());
+/// Build the JSON stats with the torrent list
+pub async fn build() -> String {
+    export::render(&stats().await, export::Format::Json, false)
+}
 
-        data.push_str(",\"torrents\":[\n");
-        let mut total_uploaded: u64 = 0;
+/// Write the stats files whose content changed or that are older than `stats_interval`, or all of them with `force`
+/// (i.e. on shutdown). They are rendered from the same snapshot.
+pub async fn write(force: bool) {
+    let config = crate::CONFIG.get().unwrap();
+    if config.output_stats.is_empty() {
+        return;
+    }
+    let stats = stats().await;
+    let mut last_writes = LAST_WRITES.lock().await;
+    for output in &config.output_stats {
+        let data = export::render(&stats, output.format, config.metrics_hide_names);
+        if !force
+            && let Some((content, time)) = last_writes.get(&output.path)
+            && *content == data
+            && time.elapsed() < Duration::from_secs(config.stats_interval)
         {
-            let torrents = TORRENTS.read().await;
-            let mut first = true;
-            for m in torrents.iter() {
-                if first {
-                    first = false;
-                } else {
-                    data.push(',');
-                }
-                let t = m.lock().await;
-                total_uploaded += t.uploaded;
-                data.push_str(&t.to_json());
-            }
+            continue;
         }
-        data.push_str("\n],\"total_uploaded\":");
-        data.push_str(&total_uploaded.to_string());
-        data.push('}');
-        if let Err(e) = tokio::fs::write(path, data.as_bytes()).await {
-            error!("Cannot write stat file: {e}");
+        match write_atomic(&output.path, data.as_bytes()).await {
+            Ok(()) => {
+                last_writes.insert(output.path.clone(), (data, Instant::now()));
+            }
+            Err(e) => error!("Cannot write stat file {}: {e}", output.path.display()),
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::json_output::writable;
+    use crate::json_output::{TorrentStats, temp_path, writable, write_atomic};
+    use crate::torrent::Torrent;
+    use std::path::Path;
+
+    #[test]
+    fn test_torrent_stats() {
+        let mut t = Torrent::from_magnet(
+            "magnet:?xt=urn:btih:0123456789abcdef0123456789abcdef01234567&dn=a%5Cb%22c%0A\
+            &tr=http://tracker.tld/announce?a=%22",
+        )
+        .unwrap();
+        t.interval = 1800;
+        let json = serde_json::to_string(&TorrentStats::new(&t, true)).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["name"], "a\\b\"c\n");
+        assert_eq!(
+            value["info_hash"],
+            "0123456789abcdef0123456789abcdef01234567"
+        );
+        assert_eq!(value["urls"][0], "http://tracker.tld/announce?a=\"");
+        assert_eq!(
+            value["trackers"][0]["last_announce"],
+            serde_json::Value::Null
+        );
+        assert_eq!(value["next_announce"], serde_json::Value::Null);
+
+        let time = chrono::Utc::now();
+        t.trackers.insert(
+            t.urls[0].clone(),
+            crate::announcer::tracker::TrackerStatus {
+                last_announce: Some(time),
+                last_error: None,
+                unregistered: false,
+            },
+        );
+        let stats = TorrentStats::new(&t, false);
+        assert_eq!(stats.last_announce, Some(time));
+        assert_eq!(
+            stats.next_announce,
+            Some(time + std::time::Duration::from_secs(1800))
+        );
+        // magnet links have no metadata
+        assert!(value.get("metadata").is_none());
+    }
+    #[tokio::test]
+    async fn test_write_atomic() {
+        let dir = std::env::temp_dir().join(format!("ratioup-stats-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("RatioUp.json");
+        write_atomic(&path, b"{}").await.unwrap();
+        write_atomic(&path, b"[]").await.unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"[]");
+        assert!(!temp_path(&path).exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
     #[test]
     fn test_writable() {
-        assert!(writable("/dev/null"));
+        assert!(writable(Path::new("/dev/null")).is_ok());
 
         // case with non writable folder
         let unwritable = "/tmp/unwritable";
@@ -84,9 +287,10 @@ mod tests {
         let mut permissions = md.permissions();
         permissions.set_readonly(true);
         std::fs::set_permissions(unwritable, permissions).unwrap();
-        assert!(!writable("/tmp/unwritable/ratioup.json"));
+        assert!(writable(Path::new("/tmp/unwritable/ratioup.json")).is_err());
 
         // case when folder does not exists
-        assert!(!writable("/aze/rty/uio/pqs/ratioup.json"));
+        assert!(writable(Path::new("/aze/rty/uio/pqs/ratioup.json")).is_err());
+        assert!(writable(Path::new("/tmp/")).is_err());
     }
 }
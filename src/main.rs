@@ -1,21 +1,32 @@
 #![allow(non_snake_case)]
 
 use fake_torrent_client::Client;
+use std::collections::BTreeMap;
 use std::path::PathBuf;
+use std::sync::Arc;
 use tokio::sync::{Mutex, OnceCell, RwLock};
 use tokio::time::Duration;
 use tracing::{self, error, info, warn};
 use utils::format_bytes;
 
 use crate::announcer::scheduler::run as run_announcer;
-use crate::config::Config;
+use crate::config::{Config, Rates};
 use crate::torrent::Torrent;
 
 mod announcer;
 pub mod bencode;
 mod config;
+mod control;
 mod directory;
+mod events;
+mod export;
+mod history;
+mod hooks;
 pub mod json_output;
+mod metadata;
+mod metrics;
+mod notify;
+mod server;
 pub mod torrent;
 mod utils;
 mod watcher;
@@ -23,11 +34,27 @@ mod watcher;
 static STARTED: OnceCell<chrono::DateTime<chrono::Utc>> = OnceCell::const_new();
 static CONFIG: OnceCell<Config> = OnceCell::const_new();
 static CLIENT: RwLock<Option<Client>> = RwLock::const_new(None);
-static TORRENTS: RwLock<Vec<Mutex<Torrent>>> = RwLock::const_new(Vec::new()); // TODO: replace with mutex
+/// Clients of the categories that do not use the global one
+static CATEGORY_CLIENTS: RwLock<BTreeMap<String, Client>> = RwLock::const_new(BTreeMap::new());
+/// Loaded torrents, a torrent can be locked alone after releasing the list, i.e. during an announce
+static TORRENTS: RwLock<Vec<Arc<Mutex<Torrent>>>> = RwLock::const_new(Vec::new());
+/// Upload rates in use, initialized from the config and editable at runtime
+static RATES: RwLock<Rates> = RwLock::const_new(Rates {
+    min_upload_rate: 0,
+    max_upload_rate: 0,
+});
 
-async fn run_key_renewer(refresh_every: u16) {
+/// Time between two scheduler runs when no torrent was announced (in seconds)
+const NO_TORRENT_WAIT_TIME: u64 = 60;
+
+/// Refresh the key of the global client, or of the client of the given category
+async fn run_key_renewer(refresh_every: u16, category: Option<String>) {
     loop {
-        if let Some(client) = &mut *CLIENT.write().await {
+        if let Some(category) = &category {
+            if let Some(client) = CATEGORY_CLIENTS.write().await.get_mut(category) {
+                client.generate_key();
+            }
+        } else if let Some(client) = &mut *CLIENT.write().await {
             client.generate_key();
         }
         // std::thread::sleep(Duration::from_secs(u64::from(refresh_every)));
@@ -35,16 +62,51 @@ async fn run_key_renewer(refresh_every: u16) {
     }
 }
 
-/// Parse CLI args. Only a config file can be there.
-fn parse_cli_args() -> Option<PathBuf> {
-    let mut args = std::env::args().skip(1); // Skip the program name
+/// What to do, according to the CLI args
+enum Command {
+    /// Seed the torrents, with an optional config file
+    Run(Option<PathBuf>),
+    /// Print the metadata of the given torrent files then exit
+    Inspect(Vec<PathBuf>),
+    /// Decode or encode a bencoded file (stdin without a path) then exit
+    Bencode(Option<PathBuf>, bencode::cli::Mode),
+}
+
+const BENCODE_USAGE: &str = "Usage: RatioUp bencode [--tree | --encode] [file | -]";
+
+/// Parse CLI args: `inspect <files>...`, `bencode [--tree | --encode] [file]`, or an optional config file to run
+/// RatioUp.
+fn parse_cli_args() -> Command {
+    let mut args = std::env::args().skip(1).peekable(); // Skip the program name
+    if args.next_if(|arg| arg == "inspect").is_some() {
+        return Command::Inspect(args.map(PathBuf::from).collect());
+    }
+    if args.next_if(|arg| arg == "bencode").is_some() {
+        let mut mode = bencode::cli::Mode::Json;
+        let mut path = None;
+        for arg in args {
+            match arg.as_str() {
+                "--tree" => mode = bencode::cli::Mode::Tree,
+                "--encode" => mode = bencode::cli::Mode::Encode,
+                "-" if path.is_none() => {}
+                other if !other.starts_with('-') && path.is_none() => {
+                    path = Some(PathBuf::from(other))
+                }
+                _ => {
+                    error!("{BENCODE_USAGE}");
+                    std::process::exit(2);
+                }
+            }
+        }
+        return Command::Bencode(path, mode);
+    }
 
     // Manually parse arguments
     while let Some(arg) = args.next() {
         match arg.as_str() {
             "-c" | "--config" => {
                 if let Some(path_str) = args.next() {
-                    return Some(PathBuf::from(path_str));
+                    return Command::Run(Some(PathBuf::from(path_str)));
                 } else {
                     tracing::error!("Missing value for -c/--config");
                 }
@@ -55,7 +117,7 @@ fn parse_cli_args() -> Option<PathBuf> {
             }
         }
     }
-    None
+    Command::Run(None)
 }
 
 fn get_config_from_xdg() -> Option<PathBuf> {
@@ -77,19 +139,55 @@ async fn main() {
         .init();
 
     // get config path if possible
-    let mut config_path: Option<PathBuf> = parse_cli_args();
+    let mut config_path: Option<PathBuf> = match parse_cli_args() {
+        Command::Run(path) => path,
+        Command::Inspect(paths) => {
+            if paths.is_empty() {
+                error!("Usage: RatioUp inspect <file.torrent>...");
+                std::process::exit(2);
+            }
+            if !metadata::inspect(&paths) {
+                std::process::exit(1);
+            }
+            return;
+        }
+        Command::Bencode(path, mode) => {
+            if !bencode::cli::run(path.as_deref(), mode) {
+                std::process::exit(1);
+            }
+            return;
+        }
+    };
     if config_path.is_none() {
         config_path = get_config_from_xdg();
     }
 
     // load config from file or default
-    let config = if let Some(path) = config_path {
+    let mut config = if let Some(path) = config_path {
         tracing::info!("Loading configuration from {}", path.display());
         Config::load_from_file(&path).await
     } else {
         tracing::info!("Loading default configuration");
         Config::default()
     };
+    config
+        .output_stats
+        .retain(|output| match json_output::writable(&output.path) {
+            Ok(()) => true,
+            Err(e) => {
+                error!("Cannot write the stats to output_stats: {e}. This file is disabled");
+                false
+            }
+        });
+    if let Some(path) = &config.history_file {
+        match json_output::writable(path) {
+            Ok(()) => history::load(path).await,
+            Err(e) => {
+                error!("Cannot write the history to history_file: {e}. It is only kept in memory");
+                config.history_file = None;
+            }
+        }
+    }
 
     info!(
         "Upload bandwidth: \u{2191} {} - {}",
@@ -101,6 +199,7 @@ async fn main() {
         error!("Cannot set config: {e}");
         return;
     }
+    *RATES.write().await = Rates::from(&config);
     if let Err(e) = STARTED.set(chrono::offset::Utc::now()) {
         error!("Cannot set start time: {e}");
         return;
@@ -108,21 +207,33 @@ async fn main() {
 
     // schedule client refresh key if applicable
     if let Some(refresh_every) = config::init_client(&config).await {
-        tokio::spawn(run_key_renewer(refresh_every));
+        tokio::spawn(run_key_renewer(refresh_every, None));
+    }
+    for (category, refresh_every) in config::init_category_clients(&config).await {
+        tokio::spawn(run_key_renewer(refresh_every, Some(category)));
     }
 
     directory::prepare_torrent_folder(config.torrent_dir.clone()).await;
-    let count = directory::load_torrents(config.torrent_dir).await;
+    let count = directory::load_torrents(config.torrent_dir.clone()).await;
     if count == 0 {
-        info!("No torrent, exiting");
-        return;
+        // torrents can be added to the watched folder or with the API
+        info!(
+            "No torrent yet, waiting for torrents in {}",
+            config.torrent_dir.display()
+        );
     }
     let mut pid_file: Option<PathBuf> = None;
     if config.use_pid_file {
         // Create PID file
         pid_file = write_pid_file().await;
     }
-    let wait_time = announcer::tracker::announce_started().await;
+    // the torrents loaded at startup are not notified
+    tokio::spawn(notify::run(events::subscribe()));
+    tokio::spawn(hooks::run(events::subscribe()));
+    // used by the scheduler when no torrent is waiting for its interval, i.e. without torrents
+    let wait_time = announcer::tracker::announce_started()
+        .await
+        .min(NO_TORRENT_WAIT_TIME);
 
     // Start file watcher for dynamic torrent management
     let watch_dir = CONFIG.get().unwrap().torrent_dir.clone();
@@ -130,11 +241,23 @@ async fn main() {
         watcher::watch_directory(watch_dir).await;
     });
 
+    tokio::spawn(control::watch_signal(config.torrent_dir.clone()));
+
+    if config.http_enabled {
+        let address = config.http_address;
+        tokio::spawn(async move {
+            server::run(address).await;
+        });
+    }
+
     tokio::spawn(async move {
         // graceful exit when Ctrl + C / SIGINT
         tokio::signal::ctrl_c().await.unwrap();
         info!("Exiting...");
         announcer::tracker::announce_stopped().await;
+        json_output::write(true).await;
+        history::save(true).await;
+        hooks::shutdown(json_output::stats().await.total_uploaded).await;
         if config.use_pid_file && pid_file.is_some() {
             remove_pid_file(pid_file).await;
         }
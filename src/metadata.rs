@@ -0,0 +1,309 @@
+// Metadata of torrent files that is not needed to announce: it is only used by the `inspect` command, the JSON stats
+// and the dashboard.
+// https://www.bittorrent.org/beps/bep_0003.html, url-list: https://www.bittorrent.org/beps/bep_0019.html
+use std::fmt::Write;
+use std::path::PathBuf;
+
+use encoding_rs::Encoding;
+use serde::Serialize;
+
+use crate::bencode::{BencodeDecoder, BencodeRef, DictRef};
+use crate::torrent::{Torrent, bencode_limits, decode_text, get_text, load_file, text_encoding};
+use crate::utils::{format_bytes_u64, to_hex};
+
+/// Optional fields of a torrent file
+#[derive(Debug, Default, PartialEq, Eq, Clone, Serialize)]
+pub struct Metadata {
+    /// Unix timestamp
+    pub creation_date: Option<i64>,
+    pub comment: Option<String>,
+    pub created_by: Option<String>,
+    pub piece_length: Option<u64>,
+    /// Tag of the tracker the torrent was made for, private trackers use it to change the info hash
+    pub source: Option<String>,
+    pub files: Vec<File>,
+    /// Web seeds (`url-list`)
+    pub web_seeds: Vec<String>,
+}
+
+/// A file of a torrent, padding files are skipped
+#[derive(Debug, PartialEq, Eq, Clone, Serialize)]
+pub struct File {
+    /// Path in the torrent, separated by `/`
+    pub path: String,
+    pub length: u64,
+}
+
+fn get_integer(dict: DictRef, key: &str) -> Option<i64> {
+    dict.get(key.as_bytes())?.as_integer()
+}
+
+/// Files of a v2 `file tree`, a file has an empty key with its `length`
+fn file_tree_files(
+    tree: DictRef,
+    prefix: &str,
+    encoding: Option<&'static Encoding>,
+    files: &mut Vec<File>,
+) {
+    for (key, value) in tree.iter() {
+        let BencodeRef::Dictionary(node) = value else {
+            continue;
+        };
+        if key.is_empty() {
+            files.push(File {
+                path: prefix.to_string(),
+                length: get_integer(node, "length").unwrap_or(0).max(0) as u64,
+            });
+        } else {
+            let name = decode_text(key, encoding);
+            let path = if prefix.is_empty() {
+                name
+            } else {
+                format!("{prefix}/{name}")
+            };
+            file_tree_files(node, &path, encoding, files);
+        }
+    }
+}
+
+impl Metadata {
+    /// Read the metadata of a torrent file, `name` is the name of the torrent. `None` if it is not a valid torrent file.
+    pub fn from_bytes(data: &[u8], name: &str) -> Option<Self> {
+        let BencodeRef::Dictionary(root) = BencodeDecoder::with_limits(data, bencode_limits())
+            .decode_ref()
+            .ok()?
+        else {
+            return None;
+        };
+        let Some(BencodeRef::Dictionary(info)) = root.get(b"info") else {
+            return None;
+        };
+        Some(Self::from_dictionaries(root, info, name))
+    }
+
+    /// Read the metadata of the source file of a torrent, it is not kept in RAM unless `stats_metadata` is enabled.
+    /// Magnet links have no metadata.
+    pub async fn of(torrent: &Torrent) -> Option<Self> {
+        if let Some(metadata) = &torrent.metadata {
+            return Some(Metadata::clone(metadata));
+        }
+        let data = tokio::fs::read(torrent.source_path.as_ref()?).await.ok()?;
+        Self::from_bytes(&data, &torrent.name)
+    }
+
+    /// Read the metadata from the top-level and `info` dictionaries of a torrent file. Invalid optional fields are
+    /// ignored, strings are decoded like the name (see [`get_text`]).
+    pub fn from_dictionaries(root: DictRef, info: DictRef, name: &str) -> Self {
+        let encoding = text_encoding(root);
+        let mut files = Vec::new();
+        if let Some(BencodeRef::Dictionary(tree)) = info.get(b"file tree") {
+            file_tree_files(tree, "", encoding, &mut files);
+        } else if let Some(BencodeRef::List(list)) = info.get(b"files") {
+            for entry in list.iter() {
+                let BencodeRef::Dictionary(entry) = entry else {
+                    continue;
+                };
+                // BEP 47: padding files of hybrid torrents
+                if get_text(entry, "attr", None).is_some_and(|attr| attr.contains('p')) {
+                    continue;
+                }
+                let path = entry.get(b"path.utf-8").or(entry.get(b"path"));
+                let Some(BencodeRef::List(parts)) = path else {
+                    continue;
+                };
+                let path = parts
+                    .iter()
+                    .filter_map(|part| match part {
+                        BencodeRef::ByteString(b) => Some(decode_text(b, encoding)),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>()
+                    .join("/");
+                files.push(File {
+                    path,
+                    length: get_integer(entry, "length").unwrap_or(0).max(0) as u64,
+                });
+            }
+        } else if let Some(length) = get_integer(info, "length") {
+            files.push(File {
+                path: name.to_string(),
+                length: length.max(0) as u64,
+            });
+        }
+
+        let web_seeds = match root.get(b"url-list") {
+            Some(BencodeRef::ByteString(url)) => vec![decode_text(url, None)],
+            Some(BencodeRef::List(urls)) => urls
+                .iter()
+                .filter_map(|url| match url {
+                    BencodeRef::ByteString(b) => Some(decode_text(b, None)),
+                    _ => None,
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        Metadata {
+            creation_date: get_integer(root, "creation date"),
+            comment: get_text(root, "comment", encoding),
+            created_by: get_text(root, "created by", encoding),
+            piece_length: get_integer(info, "piece length").and_then(|l| u64::try_from(l).ok()),
+            source: get_text(info, "source", encoding),
+            files,
+            web_seeds,
+        }
+    }
+}
+
+/// Human readable description of a torrent, for the `inspect` command
+pub fn describe(torrent: &Torrent, metadata: Option<&Metadata>) -> String {
+    let mut text = String::with_capacity(1024);
+    let _ = writeln!(text, "Name:         {}", torrent.name);
+    let _ = writeln!(text, "Info hash:    {}", to_hex(&torrent.info_hash));
+    if let Some(hash) = &torrent.info_hash_v2 {
+        let _ = writeln!(text, "Info hash v2: {}", to_hex(hash));
+    }
+    let _ = writeln!(
+        text,
+        "Size:         {} ({} bytes)",
+        format_bytes_u64(torrent.length),
+        torrent.length
+    );
+    let _ = writeln!(text, "Private:      {}", torrent.private);
+    if let Some(metadata) = metadata {
+        if let Some(length) = metadata.piece_length {
+            let _ = writeln!(text, "Piece length: {}", format_bytes_u64(length));
+        }
+        if let Some(date) = metadata
+            .creation_date
+            .and_then(|ts| chrono::DateTime::from_timestamp(ts, 0))
+        {
+            let _ = writeln!(text, "Created:      {}", date.to_rfc3339());
+        }
+        if let Some(created_by) = &metadata.created_by {
+            let _ = writeln!(text, "Created by:   {created_by}");
+        }
+        if let Some(source) = &metadata.source {
+            let _ = writeln!(text, "Source:       {source}");
+        }
+        if let Some(comment) = &metadata.comment {
+            let _ = writeln!(
+                text,
+                "Comment:      {}",
+                comment.replace('\n', "\n              ")
+            );
+        }
+    }
+    let _ = writeln!(text, "Trackers:");
+    for url in &torrent.urls {
+        let _ = writeln!(text, "  {url}");
+    }
+    if let Some(metadata) = metadata {
+        if !metadata.web_seeds.is_empty() {
+            let _ = writeln!(text, "Web seeds:");
+            for url in &metadata.web_seeds {
+                let _ = writeln!(text, "  {url}");
+            }
+        }
+        let _ = writeln!(text, "Files ({}):", metadata.files.len());
+        for file in &metadata.files {
+            let _ = writeln!(
+                text,
+                "  {:>10}  {}",
+                format_bytes_u64(file.length),
+                file.path
+            );
+        }
+    }
+    text
+}
+
+/// `inspect` command: print the torrents of the given `.torrent` or `.magnet` files, returns false if one cannot be
+/// loaded
+pub fn inspect(paths: &[PathBuf]) -> bool {
+    let mut ok = true;
+    for path in paths {
+        // the metadata is not kept when the torrent is loaded
+        let data = std::fs::read(path).unwrap_or_default();
+        for result in load_file(path) {
+            match result {
+                Ok(torrent) => {
+                    let metadata = Metadata::from_bytes(&data, &torrent.name);
+                    println!("{}", describe(&torrent, metadata.as_ref()));
+                }
+                Err(e) => {
+                    eprintln!("{}: {e}", path.display());
+                    ok = false;
+                }
+            }
+        }
+    }
+    ok
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metadata() {
+        let path = PathBuf::from("tests/Audio_20160422_archive.torrent");
+        let t = Torrent::from_file(path.clone()).unwrap();
+        // not kept without `stats_metadata`
+        assert!(t.metadata.is_none());
+        let metadata = Metadata::from_bytes(&std::fs::read(&path).unwrap(), &t.name).unwrap();
+        assert_eq!(metadata.creation_date, Some(1485625169));
+        assert_eq!(metadata.created_by.as_deref(), Some("ia_make_torrent"));
+        assert_eq!(metadata.piece_length, Some(524288));
+        assert_eq!(metadata.source, None);
+        assert_eq!(metadata.web_seeds.len(), 3);
+        assert_eq!(
+            metadata.files[0],
+            File {
+                path: String::from("Audio.rar"),
+                length: 253408921
+            }
+        );
+        assert_eq!(
+            metadata.files.iter().map(|f| f.length).sum::<u64>(),
+            t.length
+        );
+
+        // single file
+        let path = PathBuf::from("tests/ubuntu-20.04.4-desktop-amd64.iso.torrent");
+        let t = Torrent::from_file(path.clone()).unwrap();
+        let metadata = Metadata::from_bytes(&std::fs::read(&path).unwrap(), &t.name).unwrap();
+        assert_eq!(
+            metadata.comment.as_deref(),
+            Some("Ubuntu CD releases.ubuntu.com")
+        );
+        assert_eq!(metadata.files.len(), 1);
+        assert_eq!(metadata.files[0].path, t.name);
+        assert!(describe(&t, Some(&metadata)).contains("Created by:   mktorrent 1.1"));
+    }
+
+    #[test]
+    fn test_file_tree_files() {
+        let data = b"d5:a.txtd0:d6:lengthi100eee6:folderd5:b.txtd0:d6:lengthi50eeeee";
+        let tree = crate::bencode::BencodeDecoder::new(data)
+            .decode_ref()
+            .unwrap()
+            .as_dict()
+            .unwrap();
+        let mut files = Vec::new();
+        file_tree_files(tree, "", None, &mut files);
+        assert_eq!(
+            files,
+            vec![
+                File {
+                    path: String::from("a.txt"),
+                    length: 100
+                },
+                File {
+                    path: String::from("folder/b.txt"),
+                    length: 50
+                }
+            ]
+        );
+    }
+}
@@ -0,0 +1,347 @@
+// Prometheus metrics in the text exposition format
+// https://prometheus.io/docs/instrumenting/exposition_formats/
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use chrono::Utc;
+use url::Url;
+
+use crate::announcer::tracker::AnnounceError;
+use crate::json_output::TorrentStats;
+use crate::{CONFIG, TORRENTS};
+
+/// Upper bounds (in seconds) of the announce latency histogram buckets
+const LATENCY_BUCKETS: [f64; 9] = [0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0];
+
+/// Announce stats of a tracker host since the start of RatioUp
+#[derive(Debug, Default)]
+struct TrackerMetrics {
+    uploaded: u64,
+    successes: u64,
+    /// Failures by error kind
+    failures: BTreeMap<&'static str, u64>,
+    /// Non cumulative count of each bucket, the last one is `+Inf`
+    latency_buckets: [u64; LATENCY_BUCKETS.len() + 1],
+    latency_sum: f64,
+    latency_count: u64,
+}
+
+/// Name, type, help and value of a per torrent metric, `None` when it has no value yet
+type TorrentMetric = (
+    &'static str,
+    &'static str,
+    &'static str,
+    fn(&TorrentStats) -> Option<i64>,
+);
+
+static TRACKERS: Mutex<BTreeMap<String, TrackerMetrics>> = Mutex::new(BTreeMap::new());
+
+/// Get the host of a tracker URL, it is used as a label
+pub fn tracker_host(url: &str) -> String {
+    Url::parse(url)
+        .ok()
+        .and_then(|u| u.host_str().map(String::from))
+        .unwrap_or_else(|| String::from(url))
+}
+
+/// Record the result of an announce to a tracker
+pub fn record_announce(url: &str, elapsed: Duration, result: &Result<u64, AnnounceError>) {
+    let mut trackers = TRACKERS.lock().unwrap();
+    let metrics = trackers.entry(tracker_host(url)).or_default();
+    match result {
+        Ok(uploaded) => {
+            metrics.successes += 1;
+            metrics.uploaded += uploaded;
+        }
+        Err(e) => *metrics.failures.entry(e.kind()).or_default() += 1,
+    }
+    let seconds = elapsed.as_secs_f64();
+    let bucket = LATENCY_BUCKETS
+        .iter()
+        .position(|bound| seconds <= *bound)
+        .unwrap_or(LATENCY_BUCKETS.len());
+    metrics.latency_buckets[bucket] += 1;
+    metrics.latency_sum += seconds;
+    metrics.latency_count += 1;
+}
+
+/// Escape a label value (backslash, double quote and line feed)
+pub fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+pub fn write_header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+/// Build all metrics
+pub async fn render() -> String {
+    let hide_names = CONFIG.get().is_some_and(|c| c.metrics_hide_names);
+    let mut torrents: Vec<TorrentStats> = Vec::new();
+    {
+        let list = TORRENTS.read().await;
+        for m in list.iter() {
+            torrents.push(TorrentStats::new(&*m.lock().await, false));
+        }
+    }
+    let mut out = String::with_capacity(4096);
+    write_torrent_metrics(&mut out, &torrents, hide_names);
+    write_tracker_metrics(&mut out, &TRACKERS.lock().unwrap());
+    out
+}
+
+/// Per torrent metrics, shared by `/metrics` and the Prometheus stats files
+pub fn write_torrent_metrics(out: &mut String, torrents: &[TorrentStats], hide_names: bool) {
+    let labels: Vec<String> = torrents
+        .iter()
+        .map(|t| {
+            if hide_names {
+                format!("info_hash=\"{}\"", t.info_hash)
+            } else {
+                format!(
+                    "info_hash=\"{}\",name=\"{}\"",
+                    t.info_hash,
+                    escape_label(&t.name)
+                )
+            }
+        })
+        .collect();
+
+    write_header(
+        out,
+        "ratioup_torrents",
+        "gauge",
+        "Number of loaded torrents",
+    );
+    let _ = writeln!(out, "ratioup_torrents {}", torrents.len());
+
+    let metrics: [TorrentMetric; 8] = [
+        (
+            "ratioup_torrent_uploaded_bytes_total",
+            "counter",
+            "Uploaded bytes reported to trackers since the start",
+            |t| i64::try_from(t.uploaded).ok(),
+        ),
+        (
+            "ratioup_torrent_seeders",
+            "gauge",
+            "Number of seeders returned by the tracker",
+            |t| Some(t.seeders.into()),
+        ),
+        (
+            "ratioup_torrent_leechers",
+            "gauge",
+            "Number of leechers returned by the tracker",
+            |t| Some(t.leechers.into()),
+        ),
+        (
+            "ratioup_torrent_upload_rate_bytes",
+            "gauge",
+            "Current upload rate reported to trackers in bytes per second",
+            |t| Some(t.next_upload_speed.into()),
+        ),
+        (
+            "ratioup_torrent_next_announce_seconds",
+            "gauge",
+            "Time until the next announce in seconds",
+            |t| {
+                let next = t
+                    .next_announce
+                    .map(|time| (time - Utc::now()).num_seconds());
+                Some(next.unwrap_or(0).max(0))
+            },
+        ),
+        (
+            "ratioup_torrent_errors",
+            "gauge",
+            "Number of failed announces",
+            |t| Some(t.error_count.into()),
+        ),
+        (
+            "ratioup_torrent_last_announce_timestamp_seconds",
+            "gauge",
+            "Time of the last announce since the Unix epoch",
+            |t| t.last_announce.map(|time| time.timestamp()),
+        ),
+        (
+            "ratioup_torrent_next_announce_timestamp_seconds",
+            "gauge",
+            "Expected time of the next announce since the Unix epoch",
+            |t| t.next_announce.map(|time| time.timestamp()),
+        ),
+    ];
+    for (name, kind, help, value) in metrics {
+        write_header(out, name, kind, help);
+        for (t, labels) in torrents.iter().zip(&labels) {
+            if let Some(value) = value(t) {
+                let _ = writeln!(out, "{name}{{{labels}}} {value}");
+            }
+        }
+    }
+}
+
+fn write_tracker_metrics(out: &mut String, trackers: &BTreeMap<String, TrackerMetrics>) {
+    write_header(
+        out,
+        "ratioup_tracker_uploaded_bytes_total",
+        "counter",
+        "Uploaded bytes reported by tracker host",
+    );
+    for (host, m) in trackers {
+        let _ = writeln!(
+            out,
+            "ratioup_tracker_uploaded_bytes_total{{host=\"{}\"}} {}",
+            escape_label(host),
+            m.uploaded
+        );
+    }
+
+    write_header(
+        out,
+        "ratioup_announce_success_total",
+        "counter",
+        "Successful announces by tracker host",
+    );
+    for (host, m) in trackers {
+        let _ = writeln!(
+            out,
+            "ratioup_announce_success_total{{host=\"{}\"}} {}",
+            escape_label(host),
+            m.successes
+        );
+    }
+
+    write_header(
+        out,
+        "ratioup_announce_failure_total",
+        "counter",
+        "Failed announces by tracker host and error kind",
+    );
+    for (host, m) in trackers {
+        for (kind, count) in &m.failures {
+            let _ = writeln!(
+                out,
+                "ratioup_announce_failure_total{{host=\"{}\",kind=\"{kind}\"}} {count}",
+                escape_label(host),
+            );
+        }
+    }
+
+    write_header(
+        out,
+        "ratioup_announce_duration_seconds",
+        "histogram",
+        "Announce latency by tracker host",
+    );
+    for (host, m) in trackers {
+        let host = escape_label(host);
+        let mut cumulative = 0u64;
+        for (i, bound) in LATENCY_BUCKETS.iter().enumerate() {
+            cumulative += m.latency_buckets[i];
+            let _ = writeln!(
+                out,
+                "ratioup_announce_duration_seconds_bucket{{host=\"{host}\",le=\"{bound}\"}} {cumulative}"
+            );
+        }
+        let _ = writeln!(
+            out,
+            "ratioup_announce_duration_seconds_bucket{{host=\"{host}\",le=\"+Inf\"}} {}",
+            m.latency_count
+        );
+        let _ = writeln!(
+            out,
+            "ratioup_announce_duration_seconds_sum{{host=\"{host}\"}} {}",
+            m.latency_sum
+        );
+        let _ = writeln!(
+            out,
+            "ratioup_announce_duration_seconds_count{{host=\"{host}\"}} {}",
+            m.latency_count
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escape_label() {
+        assert_eq!(escape_label("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
+    }
+
+    #[test]
+    fn test_tracker_host() {
+        assert_eq!(
+            tracker_host("udp://tracker.example.com:6969/announce"),
+            "tracker.example.com"
+        );
+        assert_eq!(tracker_host("not an url"), "not an url");
+    }
+
+    #[test]
+    fn test_torrent_metrics() {
+        let mut t = crate::torrent::Torrent::from_file(std::path::PathBuf::from(
+            "tests/Audio_20160422_archive.torrent",
+        ))
+        .unwrap();
+        t.uploaded = 1024;
+        t.seeders = 3;
+        let t = TorrentStats::new(&t, false);
+        let mut out = String::new();
+        write_torrent_metrics(&mut out, std::slice::from_ref(&t), false);
+        let hash = t.info_hash.clone();
+        assert!(out.contains("ratioup_torrents 1\n"));
+        assert!(out.contains(&format!(
+            "ratioup_torrent_uploaded_bytes_total{{info_hash=\"{hash}\",name=\"{}\"}} 1024\n",
+            escape_label(&t.name)
+        )));
+
+        let mut out = String::new();
+        write_torrent_metrics(&mut out, &[t], true);
+        assert!(out.contains(&format!(
+            "ratioup_torrent_seeders{{info_hash=\"{hash}\"}} 3\n"
+        )));
+        // not announced yet
+        assert!(!out.contains("ratioup_torrent_last_announce_timestamp_seconds{"));
+        assert!(out.contains(&format!(
+            "ratioup_torrent_next_announce_seconds{{info_hash=\"{hash}\"}} 0\n"
+        )));
+    }
+
+    #[test]
+    fn test_tracker_metrics() {
+        let mut trackers = BTreeMap::new();
+        let mut m = TrackerMetrics {
+            uploaded: 4096,
+            successes: 2,
+            latency_count: 3,
+            latency_sum: 120.05,
+            ..Default::default()
+        };
+        m.failures.insert("timeout", 1);
+        m.latency_buckets[0] = 1;
+        m.latency_buckets[LATENCY_BUCKETS.len()] = 2;
+        trackers.insert(String::from("tracker.tld"), m);
+        let mut out = String::new();
+        write_tracker_metrics(&mut out, &trackers);
+        assert!(out.contains("ratioup_tracker_uploaded_bytes_total{host=\"tracker.tld\"} 4096\n"));
+        assert!(
+            out.contains(
+                "ratioup_announce_failure_total{host=\"tracker.tld\",kind=\"timeout\"} 1\n"
+            )
+        );
+        assert!(out.contains(
+            "ratioup_announce_duration_seconds_bucket{host=\"tracker.tld\",le=\"60\"} 1\n"
+        ));
+        assert!(out.contains(
+            "ratioup_announce_duration_seconds_bucket{host=\"tracker.tld\",le=\"+Inf\"} 3\n"
+        ));
+    }
+}
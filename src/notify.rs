@@ -0,0 +1,356 @@
+// Notifications sent to webhooks (`[[webhooks]]`) for the important events of the event bus: tracker failures,
+// repeated errors, torrents added or removed, stop rules reached, and a daily summary at midnight (UTC).
+use std::time::Duration;
+
+use chrono::{Days, Utc};
+use reqwest::Client;
+use serde_json::json;
+use tokio::sync::broadcast::Receiver;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{error, warn};
+
+use crate::events::BusEvent;
+use crate::utils::format_bytes_u64;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// Kinds of notifications, they can be selected with `events` in a webhook
+pub const KINDS: [&str; 7] = [
+    "tracker_failure",
+    "unregistered",
+    "errors",
+    "torrent_added",
+    "torrent_removed",
+    "stop_rule",
+    "daily_summary",
+];
+
+/// Payload of a webhook
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WebhookFormat {
+    /// `{"event", "title", "message", "time"}`
+    Json,
+    /// Text body with a `Title` header
+    Ntfy,
+    /// `{"title", "message", "priority"}`, the token is in the URL
+    Gotify,
+    /// `{"content"}`
+    Discord,
+}
+
+impl WebhookFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "json" => Some(WebhookFormat::Json),
+            "ntfy" => Some(WebhookFormat::Ntfy),
+            "gotify" => Some(WebhookFormat::Gotify),
+            "discord" => Some(WebhookFormat::Discord),
+            _ => None,
+        }
+    }
+}
+
+/// A webhook of `[[webhooks]]`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Webhook {
+    pub url: String,
+    pub format: WebhookFormat,
+    /// Kinds of notifications sent to it, all of them when empty
+    pub events: Vec<String>,
+}
+
+impl Webhook {
+    fn accepts(&self, kind: &str) -> bool {
+        self.events.is_empty() || self.events.iter().any(|e| e == kind)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Notification {
+    /// One of [`KINDS`]
+    pub kind: &'static str,
+    pub title: String,
+    pub message: String,
+}
+
+impl Notification {
+    /// Notification of an event of the bus, if it is an important one. `threshold` is the number of errors of a
+    /// torrent to notify, whatever their kind. A rejected announce is only notified the first time, and not when the
+    /// torrent is not registered (it has its own notification).
+    pub fn from_event(event: &BusEvent, threshold: u16) -> Option<Self> {
+        let (kind, title, message) = match event {
+            BusEvent::TrackerError {
+                name,
+                host,
+                message,
+                error_count,
+                ..
+            } if threshold > 0 && *error_count == threshold => (
+                "errors",
+                format!("{error_count} announce errors"),
+                format!("{name}: last error from {host}: {message}"),
+            ),
+            BusEvent::TrackerError {
+                name,
+                host,
+                kind: "tracker",
+                message,
+                first: true,
+                unregistered: false,
+                ..
+            } => (
+                "tracker_failure",
+                format!("Tracker {host} rejected an announce"),
+                format!("{name}: {message}"),
+            ),
+            BusEvent::TorrentUnregistered {
+                name,
+                host,
+                message,
+                ..
+            } => (
+                "unregistered",
+                format!("Torrent not registered on {host}"),
+                format!("{name}: {message}"),
+            ),
+            BusEvent::TorrentAdded { name, .. } => {
+                ("torrent_added", String::from("Torrent added"), name.clone())
+            }
+            BusEvent::TorrentRemoved { name, uploaded, .. } => (
+                "torrent_removed",
+                String::from("Torrent removed"),
+                format!("{name}: {} uploaded", format_bytes_u64(*uploaded)),
+            ),
+            BusEvent::StopRuleReached { name, uploaded, .. } => (
+                "stop_rule",
+                String::from("Stop rule reached"),
+                format!("{name}: {} uploaded", format_bytes_u64(*uploaded)),
+            ),
+            _ => return None,
+        };
+        Some(Notification {
+            kind,
+            title,
+            message,
+        })
+    }
+}
+
+/// Body, content type and title header of the request to a webhook
+fn request(format: WebhookFormat, n: &Notification) -> (String, &'static str, Option<String>) {
+    match format {
+        WebhookFormat::Json => (
+            json!({"event": n.kind, "title": n.title, "message": n.message, "time": Utc::now()})
+                .to_string(),
+            "application/json",
+            None,
+        ),
+        WebhookFormat::Ntfy => (n.message.clone(), "text/plain", Some(n.title.clone())),
+        WebhookFormat::Gotify => (
+            json!({"title": n.title, "message": n.message, "priority": 5}).to_string(),
+            "application/json",
+            None,
+        ),
+        WebhookFormat::Discord => (
+            json!({"content": format!("**{}**\n{}", n.title, n.message)}).to_string(),
+            "application/json",
+            None,
+        ),
+    }
+}
+
+/// Send a notification to the webhooks that accept it, in the background so a slow webhook does not delay the others
+fn send(client: &Client, webhooks: &[Webhook], notification: &Notification) {
+    for webhook in webhooks.iter().filter(|w| w.accepts(notification.kind)) {
+        let (body, content_type, title) = request(webhook.format, notification);
+        let mut builder = client
+            .post(&webhook.url)
+            .header("Content-Type", content_type)
+            .body(body);
+        if let Some(title) = title {
+            builder = builder.header("Title", title);
+        }
+        let url = webhook.url.clone();
+        tokio::spawn(async move {
+            match builder.send().await {
+                Ok(response) if !response.status().is_success() => {
+                    warn!("Webhook {url} returned {}", response.status());
+                }
+                Ok(_) => {}
+                Err(e) => warn!("Cannot send notification to {url}: {e}"),
+            }
+        });
+    }
+}
+
+/// Summary of the stats and of the upload of the day that just ended
+async fn daily_summary() -> Notification {
+    let stats = crate::json_output::stats().await;
+    // it is sent at midnight, the last day of the history is the new one
+    let yesterday: u64 = crate::history::daily_upload_by_tracker()
+        .iter()
+        .filter_map(|t| t.daily_uploaded.iter().rev().nth(1))
+        .sum();
+    let errors = stats
+        .torrents
+        .iter()
+        .filter(|t| t.last_error.is_some())
+        .count();
+    Notification {
+        kind: "daily_summary",
+        title: String::from("RatioUp daily summary"),
+        message: format!(
+            "{} torrents ({} paused, {errors} with errors), {} uploaded yesterday, {} since the start",
+            stats.torrents.len(),
+            stats.torrents.iter().filter(|t| t.paused).count(),
+            format_bytes_u64(yesterday),
+            format_bytes_u64(stats.total_uploaded)
+        ),
+    }
+}
+
+/// Time until the next midnight (UTC)
+fn until_midnight() -> Duration {
+    let now = Utc::now();
+    now.date_naive()
+        .checked_add_days(Days::new(1))
+        .and_then(|day| day.and_hms_opt(0, 0, 0))
+        .and_then(|midnight| (midnight.and_utc() - now).to_std().ok())
+        .unwrap_or(Duration::from_secs(24 * 3600))
+}
+
+/// Send the notifications of the events received on the bus, and the daily summaries
+pub async fn run(mut rx: Receiver<BusEvent>) {
+    let Some(config) = crate::CONFIG.get().filter(|c| !c.webhooks.is_empty()) else {
+        return;
+    };
+    let client = match Client::builder().timeout(TIMEOUT).build() {
+        Ok(client) => client,
+        Err(e) => {
+            error!("Cannot build the webhook client: {e}");
+            return;
+        }
+    };
+    let summary = tokio::time::sleep(until_midnight());
+    tokio::pin!(summary);
+    loop {
+        tokio::select! {
+            received = rx.recv() => match received {
+                Ok(event) => {
+                    if let Some(n) = Notification::from_event(&event, config.notify_error_threshold) {
+                        send(&client, &config.webhooks, &n);
+                    }
+                }
+                Err(RecvError::Lagged(count)) => warn!("Notifications missed {count} event(s)"),
+                Err(RecvError::Closed) => break,
+            },
+            _ = &mut summary => {
+                send(&client, &config.webhooks, &daily_summary().await);
+                summary.as_mut().reset(tokio::time::Instant::now() + until_midnight());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tracker_error(
+        kind: &'static str,
+        error_count: u16,
+        first: bool,
+        unregistered: bool,
+    ) -> BusEvent {
+        BusEvent::TrackerError {
+            info_hash: String::from("aa"),
+            name: String::from("linux.iso"),
+            host: String::from("tracker.tld"),
+            kind,
+            message: String::from("tracker error: torrent banned"),
+            error_count,
+            first,
+            unregistered,
+        }
+    }
+
+    #[test]
+    fn test_from_event() {
+        let n = Notification::from_event(&tracker_error("tracker", 1, true, false), 5).unwrap();
+        assert_eq!(n.kind, "tracker_failure");
+        assert_eq!(n.message, "linux.iso: tracker error: torrent banned");
+        // only the first failure is notified
+        assert_eq!(
+            Notification::from_event(&tracker_error("tracker", 2, false, false), 5),
+            None
+        );
+        // network errors are only notified when the threshold is reached
+        assert_eq!(
+            Notification::from_event(&tracker_error("network", 1, true, false), 5),
+            None
+        );
+        for kind in ["network", "tracker"] {
+            assert_eq!(
+                Notification::from_event(&tracker_error(kind, 5, false, false), 5)
+                    .unwrap()
+                    .kind,
+                "errors"
+            );
+        }
+        assert_eq!(
+            Notification::from_event(&tracker_error("network", 5, false, false), 0),
+            None
+        );
+        // it has its own notification
+        assert_eq!(
+            Notification::from_event(&tracker_error("tracker", 1, true, true), 5),
+            None
+        );
+        let removed = BusEvent::TorrentRemoved {
+            info_hash: String::from("aa"),
+            name: String::from("linux.iso"),
+            uploaded: 2048,
+        };
+        assert_eq!(
+            Notification::from_event(&removed, 5).unwrap().kind,
+            "torrent_removed"
+        );
+        assert_eq!(
+            Notification::from_event(
+                &BusEvent::RatesChanged {
+                    min_upload_rate: 1,
+                    max_upload_rate: 2
+                },
+                5
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn test_request() {
+        let n = Notification {
+            kind: "torrent_added",
+            title: String::from("Torrent added"),
+            message: String::from("linux.iso"),
+        };
+        let (body, content_type, title) = request(WebhookFormat::Ntfy, &n);
+        assert_eq!(
+            (body.as_str(), content_type, title.as_deref()),
+            ("linux.iso", "text/plain", Some("Torrent added"))
+        );
+        let (body, _, title) = request(WebhookFormat::Discord, &n);
+        assert_eq!(body, r#"{"content":"**Torrent added**\nlinux.iso"}"#);
+        assert_eq!(title, None);
+        let (body, ..) = request(WebhookFormat::Json, &n);
+        let value: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(value["event"], "torrent_added");
+
+        let webhook = Webhook {
+            url: String::from("http://localhost"),
+            format: WebhookFormat::Gotify,
+            events: vec![String::from("stop_rule")],
+        };
+        assert!(webhook.accepts("stop_rule"));
+        assert!(!webhook.accepts("torrent_added"));
+    }
+}
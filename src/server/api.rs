@@ -0,0 +1,366 @@
+// REST API to manage a running instance:
+// - GET    /api/torrents                  list torrents with their stats
+// - POST   /api/torrents                  add a torrent, the body is the .torrent file
+// - GET    /api/torrents/{hash}           get a torrent, with the metadata of its file
+// - DELETE /api/torrents/{hash}           remove a torrent (and its file, unless other magnet links are in it)
+// - POST   /api/torrents/{hash}/pause     pause a torrent (announce STOPPED)
+// - POST   /api/torrents/{hash}/resume    resume a torrent (announce STARTED)
+// - POST   /api/torrents/{hash}/announce  force an announce if `min interval` allows it
+// - GET    /api/rates                     get upload rates
+// - PATCH  /api/rates                     change upload rates
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+use super::{Request, Response};
+use crate::announcer::tracker::announce;
+use crate::config::Rates;
+use crate::events::{self, BusEvent};
+use crate::metadata::Metadata;
+use crate::torrent::{Torrent, paused_marker, unregistered_marker};
+use crate::utils::{info_hash_from_hex, to_hex};
+use crate::watcher::{
+    AddError, add_torrent, find_torrent, hashes_from_path, remove_torrent, set_paused,
+};
+use crate::{CONFIG, RATES, TORRENTS};
+
+/// Torrent as returned by the API
+#[derive(Debug, Serialize)]
+struct TorrentInfo {
+    info_hash: String,
+    /// SHA-256 info hash of v2 and hybrid torrents
+    info_hash_v2: Option<String>,
+    name: String,
+    length: u64,
+    private: bool,
+    paused: bool,
+    category: Option<String>,
+    uploaded: u64,
+    seeders: u16,
+    leechers: u16,
+    next_upload_speed: u32,
+    interval: u64,
+    min_interval: Option<u64>,
+    /// Seconds since the last announce
+    last_announce: u64,
+    error_count: u16,
+    urls: Vec<String>,
+    /// Only for a single torrent, it is read from its file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    metadata: Option<Metadata>,
+}
+
+impl From<&Torrent> for TorrentInfo {
+    fn from(t: &Torrent) -> Self {
+        TorrentInfo {
+            info_hash: to_hex(&t.info_hash),
+            info_hash_v2: t.info_hash_v2.as_ref().map(|h| to_hex(h)),
+            name: t.name.clone(),
+            length: t.length,
+            private: t.private,
+            paused: t.paused,
+            category: t.category.clone(),
+            uploaded: t.uploaded,
+            seeders: t.seeders,
+            leechers: t.leechers,
+            next_upload_speed: t.next_upload_speed,
+            interval: t.interval,
+            min_interval: t.min_interval,
+            last_announce: t.last_announce.elapsed().as_secs(),
+            error_count: t.error_count,
+            urls: t.urls.clone(),
+            metadata: None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct RatesBody {
+    min_upload_rate: Option<u32>,
+    max_upload_rate: Option<u32>,
+}
+
+fn to_json<T: Serialize>(status: u16, value: &T) -> Response {
+    match serde_json::to_string(value) {
+        Ok(body) => Response::json(status, body),
+        Err(e) => Response::error(500, &e.to_string()),
+    }
+}
+
+pub async fn handle(request: &Request) -> Response {
+    let segments = request.segments();
+    let method = request.method.as_str();
+    match segments.as_slice() {
+        ["api", "torrents"] => match method {
+            "GET" => list_torrents().await,
+            "POST" => add(&request.body).await,
+            _ => Response::method_not_allowed(),
+        },
+        ["api", "torrents", hash] => {
+            let Some(hash) = info_hash_from_hex(hash) else {
+                return Response::error(400, "Invalid info hash");
+            };
+            match method {
+                "GET" => get_torrent(&hash).await,
+                "DELETE" => remove(&hash).await,
+                _ => Response::method_not_allowed(),
+            }
+        }
+        ["api", "torrents", hash, action] => {
+            let Some(hash) = info_hash_from_hex(hash) else {
+                return Response::error(400, "Invalid info hash");
+            };
+            if method != "POST" {
+                return Response::method_not_allowed();
+            }
+            match *action {
+                "pause" => pause(&hash, true).await,
+                "resume" => pause(&hash, false).await,
+                "announce" => force_announce(&hash).await,
+                _ => Response::not_found(),
+            }
+        }
+        ["api", "rates"] => match method {
+            "GET" => get_rates().await,
+            "PATCH" => patch_rates(&request.body).await,
+            _ => Response::method_not_allowed(),
+        },
+        _ => Response::not_found(),
+    }
+}
+
+/// Find the index of the torrent with the given info hash
+async fn find_index(list: &[Arc<Mutex<Torrent>>], info_hash: &[u8; 20]) -> Option<usize> {
+    for (i, m) in list.iter().enumerate() {
+        if &m.lock().await.info_hash == info_hash {
+            return Some(i);
+        }
+    }
+    None
+}
+
+async fn list_torrents() -> Response {
+    let list = TORRENTS.read().await;
+    let mut torrents: Vec<TorrentInfo> = Vec::with_capacity(list.len());
+    for m in list.iter() {
+        torrents.push(TorrentInfo::from(&*m.lock().await));
+    }
+    to_json(200, &torrents)
+}
+
+async fn get_torrent(info_hash: &[u8; 20]) -> Response {
+    let torrent = {
+        let list = TORRENTS.read().await;
+        match find_index(&list, info_hash).await {
+            Some(i) => list[i].lock().await.clone(),
+            None => return Response::not_found(),
+        }
+    };
+    // the file is read without locking the list
+    let mut info = TorrentInfo::from(&torrent);
+    info.metadata = Metadata::of(&torrent).await;
+    to_json(200, &info)
+}
+
+async fn add(body: &[u8]) -> Response {
+    let mut torrent = match Torrent::from_bencode_bytes(body) {
+        Ok(t) => t,
+        Err(e) => return Response::error(400, &format!("Invalid torrent: {e}")),
+    };
+    let info_hash = torrent.info_hash;
+
+    // keep a copy in the torrent directory so it is loaded on next start
+    let config = CONFIG.get().unwrap();
+    let Some(path) = [
+        sanitize_filename::sanitize(&torrent.name),
+        to_hex(&info_hash),
+    ]
+    .iter()
+    .map(|name| config.torrent_dir.join(format!("{name}.torrent")))
+    .find(|path| !path.exists()) else {
+        return Response::error(409, "A torrent file with this name already exists");
+    };
+    torrent.source_path = Some(path.clone());
+
+    match add_torrent(torrent).await {
+        Ok(()) => {
+            // the file watcher will find the torrent already loaded. An existing file is never overwritten.
+            if let Err(e) = write_new(&path, body).await {
+                warn!("Cannot save torrent file {}: {e}", path.display());
+            }
+            info!("Torrent added from API: {}", path.display());
+            get_torrent(&info_hash).await.with_status(201)
+        }
+        Err(AddError::Duplicate) => Response::error(409, &AddError::Duplicate.to_string()),
+        Err(e) => Response::error(422, &e.to_string()),
+    }
+}
+
+/// Write a file that must not exist yet
+async fn write_new(path: &std::path::Path, content: &[u8]) -> std::io::Result<()> {
+    let mut file = tokio::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .await?;
+    file.write_all(content).await
+}
+
+async fn remove(info_hash: &[u8; 20]) -> Response {
+    match remove_torrent(info_hash).await {
+        Some(torrent) => {
+            // other magnet links of the same file are still loaded
+            if let Some(path) = torrent.source_path
+                && hashes_from_path(&path).await.is_empty()
+            {
+                if let Err(e) = tokio::fs::remove_file(&path).await {
+                    warn!("Cannot remove torrent file {}: {e}", path.display());
+                }
+                let _ = tokio::fs::remove_file(paused_marker(&path)).await;
+                let _ = tokio::fs::remove_file(unregistered_marker(&path)).await;
+            }
+            Response::no_content()
+        }
+        None => Response::not_found(),
+    }
+}
+
+async fn pause(info_hash: &[u8; 20], paused: bool) -> Response {
+    if set_paused(info_hash, paused).await {
+        get_torrent(info_hash).await
+    } else {
+        Response::not_found()
+    }
+}
+
+async fn force_announce(info_hash: &[u8; 20]) -> Response {
+    // only the torrent is locked during the announce
+    let Some(torrent) = find_torrent(info_hash).await else {
+        return Response::not_found();
+    };
+    let mut t = torrent.lock().await;
+    if t.paused {
+        return Response::error(409, "Torrent is paused");
+    }
+    if !t.can_reannounce() {
+        let wait = t
+            .min_interval
+            .unwrap_or(0)
+            .saturating_sub(t.last_announce.elapsed().as_secs());
+        let mut response = Response::error(429, "Minimum announce interval not reached");
+        response
+            .headers
+            .push((String::from("Retry-After"), wait.to_string()));
+        return response;
+    }
+    announce(&mut t, None).await;
+    to_json(200, &TorrentInfo::from(&*t))
+}
+
+fn rates_body(rates: &Rates) -> RatesBody {
+    RatesBody {
+        min_upload_rate: Some(rates.min_upload_rate),
+        max_upload_rate: Some(rates.max_upload_rate),
+    }
+}
+
+async fn get_rates() -> Response {
+    to_json(200, &rates_body(&*RATES.read().await))
+}
+
+async fn patch_rates(body: &[u8]) -> Response {
+    let patch: RatesBody = match serde_json::from_slice(body) {
+        Ok(p) => p,
+        Err(e) => return Response::error(400, &format!("Invalid JSON: {e}")),
+    };
+    let mut rates = RATES.write().await;
+    let updated = apply_rates(&rates, &patch);
+    if !updated.is_valid() {
+        return Response::error(
+            422,
+            "min_upload_rate must be lower or equal to max_upload_rate",
+        );
+    }
+    *rates = updated;
+    events::publish(BusEvent::RatesChanged {
+        min_upload_rate: rates.min_upload_rate,
+        max_upload_rate: rates.max_upload_rate,
+    });
+    info!(
+        "Upload rates changed: {} - {}",
+        crate::utils::format_bytes(rates.min_upload_rate),
+        crate::utils::format_bytes(rates.max_upload_rate)
+    );
+    to_json(200, &rates_body(&rates))
+}
+
+fn apply_rates(rates: &Rates, patch: &RatesBody) -> Rates {
+    Rates {
+        min_upload_rate: patch.min_upload_rate.unwrap_or(rates.min_upload_rate),
+        max_upload_rate: patch.max_upload_rate.unwrap_or(rates.max_upload_rate),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(method: &str, path: &str) -> Request {
+        Request {
+            method: String::from(method),
+            path: String::from(path),
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_routing_errors() {
+        let response = handle(&request("GET", "/api/unknown")).await;
+        assert_eq!(response.status, 404);
+        let response = handle(&request("PUT", "/api/torrents")).await;
+        assert_eq!(response.status, 405);
+        let response = handle(&request("GET", "/api/torrents/not-a-hash")).await;
+        assert_eq!(response.status, 400);
+        let response = handle(&request(
+            "POST",
+            "/api/torrents/0000000000000000000000000000000000000000/pause",
+        ))
+        .await;
+        assert_eq!(response.status, 404);
+    }
+
+    #[test]
+    fn test_apply_rates() {
+        let rates = Rates {
+            min_upload_rate: 1024,
+            max_upload_rate: 4096,
+        };
+        let patch = RatesBody {
+            min_upload_rate: None,
+            max_upload_rate: Some(2048),
+        };
+        let updated = apply_rates(&rates, &patch);
+        assert_eq!(updated.min_upload_rate, 1024);
+        assert_eq!(updated.max_upload_rate, 2048);
+        assert!(updated.is_valid());
+
+        let patch = RatesBody {
+            min_upload_rate: Some(8192),
+            max_upload_rate: None,
+        };
+        assert!(!apply_rates(&rates, &patch).is_valid());
+    }
+
+    #[tokio::test]
+    async fn test_write_new() {
+        let path = std::env::temp_dir().join(format!("ratioup-api-{}.torrent", std::process::id()));
+        write_new(&path, b"first").await.unwrap();
+        assert!(write_new(&path, b"second").await.is_err());
+        assert_eq!(std::fs::read(&path).unwrap(), b"first");
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,44 @@
+// Stats dashboard embedded in the binary, so there is no need to copy `www/index.html` and to run another web server.
+use super::{Request, Response};
+use crate::export::INDEX_HTML;
+use crate::json_output;
+
+pub async fn handle(request: &Request) -> Response {
+    if request.method != "GET" {
+        return Response::method_not_allowed();
+    }
+    match request.path.as_str() {
+        "/" | "/index.html" => Response::new(
+            200,
+            "text/html; charset=utf-8",
+            INDEX_HTML.as_bytes().to_vec(),
+        ),
+        // same name as the file used by the dashboard, it is built on each request so it is always up to date
+        "/RatioUp.json" => {
+            let mut response = Response::json(200, json_output::build().await);
+            response
+                .headers
+                .push((String::from("Cache-Control"), String::from("no-store")));
+            response
+        }
+        _ => Response::not_found(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_index() {
+        let request = Request {
+            method: String::from("GET"),
+            path: String::from("/"),
+            headers: Vec::new(),
+            body: Vec::new(),
+        };
+        let response = handle(&request).await;
+        assert_eq!(response.status, 200);
+        assert!(response.body.starts_with(b"<!DOCTYPE html>"));
+    }
+}
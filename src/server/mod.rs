@@ -0,0 +1,427 @@
+// Minimal embedded HTTP/1.1 server.
+// It is intentionally small (one request per connection, no keep-alive) to keep RatioUp light, it is only meant to be
+// used on localhost or on a LAN.
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::timeout;
+use tracing::{debug, error, info, warn};
+
+mod api;
+mod dashboard;
+
+/// Maximum size of a request body (a .torrent file is usually far smaller)
+const MAX_BODY_SIZE: usize = 10 * 1024 * 1024;
+/// Maximum number of headers we accept
+const MAX_HEADERS: usize = 64;
+/// Maximum length of the request line and of a header line
+const MAX_LINE: usize = 8 * 1024;
+/// Time allowed to the client to send the request
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+/// Send a comment to Server-Sent Events clients when there is no event for this duration
+const SSE_KEEP_ALIVE: Duration = Duration::from_secs(30);
+
+#[derive(Debug)]
+pub struct Request {
+    pub method: String,
+    /// Path without the query string
+    pub path: String,
+    /// Headers with lowercase names
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    /// Get a header value, the name must be lowercase
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Path segments without empty ones, i.e. `/api/torrents/` gives `["api", "torrents"]`
+    pub fn segments(&self) -> Vec<&str> {
+        self.path.split('/').filter(|s| !s.is_empty()).collect()
+    }
+}
+
+#[derive(Debug)]
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn new(status: u16, content_type: &'static str, body: Vec<u8>) -> Self {
+        Response {
+            status,
+            content_type,
+            headers: Vec::new(),
+            body,
+        }
+    }
+
+    pub fn json(status: u16, body: String) -> Self {
+        Self::new(status, "application/json", body.into_bytes())
+    }
+
+    /// JSON error message: `{"error": "<message>"}`
+    pub fn error(status: u16, message: &str) -> Self {
+        Self::json(status, serde_json::json!({ "error": message }).to_string())
+    }
+
+    pub fn with_status(mut self, status: u16) -> Self {
+        self.status = status;
+        self
+    }
+
+    pub fn no_content() -> Self {
+        Self::new(204, "text/plain", Vec::new())
+    }
+
+    pub fn not_found() -> Self {
+        Self::error(404, "Not found")
+    }
+
+    pub fn method_not_allowed() -> Self {
+        Self::error(405, "Method not allowed")
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut head = format!(
+            "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+            self.status,
+            reason_phrase(self.status),
+            self.content_type,
+            self.body.len()
+        );
+        for (name, value) in &self.headers {
+            head.push_str(name);
+            head.push_str(": ");
+            head.push_str(value);
+            head.push_str("\r\n");
+        }
+        head.push_str("\r\n");
+        let mut bytes = head.into_bytes();
+        bytes.extend_from_slice(&self.body);
+        bytes
+    }
+}
+
+fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        204 => "No Content",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        413 => "Payload Too Large",
+        431 => "Request Header Fields Too Large",
+        422 => "Unprocessable Entity",
+        429 => "Too Many Requests",
+        500 => "Internal Server Error",
+        _ => "Unknown",
+    }
+}
+
+/// Errors while reading a request, the connection is closed after answering with the status code
+#[derive(Debug, PartialEq)]
+enum RequestError {
+    BadRequest,
+    TooLarge,
+    /// The request line or a header line is longer than [`MAX_LINE`]
+    LineTooLong,
+    Closed,
+}
+
+/// Start the HTTP server, it never returns unless the address cannot be bound.
+pub async fn run(address: SocketAddr) {
+    let listener = match TcpListener::bind(address).await {
+        Ok(l) => l,
+        Err(e) => {
+            error!("Cannot start HTTP server on {address}: {e}");
+            return;
+        }
+    };
+    if !address.ip().is_loopback() {
+        warn!("HTTP server is not bound to localhost, it is reachable from the network");
+    }
+    info!("HTTP server listening on http://{address}");
+    loop {
+        match listener.accept().await {
+            Ok((stream, peer)) => {
+                tokio::spawn(async move {
+                    handle_connection(stream, peer).await;
+                });
+            }
+            Err(e) => error!("Cannot accept HTTP connection: {e}"),
+        }
+    }
+}
+
+async fn handle_connection(mut stream: TcpStream, peer: SocketAddr) {
+    let response = match timeout(READ_TIMEOUT, read_request(&mut stream)).await {
+        Ok(Ok(request)) => {
+            debug!("HTTP {} {} from {peer}", request.method, request.path);
+            match unauthorized(&request) {
+                Some(response) => response,
+                None if request.method == "GET" && request.path == "/events" => {
+                    stream_events(stream, peer).await;
+                    return;
+                }
+                None => route(&request).await,
+            }
+        }
+        Ok(Err(RequestError::Closed)) => return,
+        Ok(Err(RequestError::TooLarge)) => Response::error(413, "Request too large"),
+        Ok(Err(RequestError::LineTooLong)) => {
+            Response::error(431, "Request line or header too long")
+        }
+        Ok(Err(RequestError::BadRequest)) => Response::error(400, "Bad request"),
+        Err(_) => return, // timeout
+    };
+    if let Err(e) = stream.write_all(&response.to_bytes()).await {
+        debug!("Cannot write HTTP response to {peer}: {e}");
+    }
+    let _ = stream.shutdown().await;
+}
+
+/// Keep the connection open and send bus events as Server-Sent Events until the client disconnects
+async fn stream_events(mut stream: TcpStream, peer: SocketAddr) {
+    let mut rx = crate::events::subscribe();
+    let head = "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: keep-alive\r\n\r\n";
+    if stream.write_all(head.as_bytes()).await.is_err() {
+        return;
+    }
+    debug!("SSE client connected: {peer}");
+    loop {
+        let message = match timeout(SSE_KEEP_ALIVE, rx.recv()).await {
+            Ok(Ok(event)) => match serde_json::to_string(&event) {
+                Ok(data) => format!("event: {}\ndata: {data}\n\n", event.name()),
+                Err(e) => {
+                    error!("Cannot serialize event: {e}");
+                    continue;
+                }
+            },
+            Ok(Err(RecvError::Lagged(count))) => {
+                warn!("SSE client {peer} missed {count} event(s)");
+                continue;
+            }
+            Ok(Err(RecvError::Closed)) => break,
+            // comment line to keep the connection alive through proxies
+            Err(_) => String::from(": keep-alive\n\n"),
+        };
+        if stream.write_all(message.as_bytes()).await.is_err() {
+            break;
+        }
+    }
+    debug!("SSE client disconnected: {peer}");
+}
+
+/// Returns a 401 response if basic authentication is configured and the request credentials are wrong
+fn unauthorized(request: &Request) -> Option<Response> {
+    let config = crate::CONFIG.get().unwrap();
+    if let (Some(username), Some(password)) = (&config.http_username, &config.http_password)
+        && !is_authorized(request, username, password)
+    {
+        let mut response = Response::error(401, "Unauthorized");
+        response.headers.push((
+            String::from("WWW-Authenticate"),
+            String::from("Basic realm=\"RatioUp\", charset=\"UTF-8\""),
+        ));
+        return Some(response);
+    }
+    None
+}
+
+/// Dispatch the request to the matching module
+async fn route(request: &Request) -> Response {
+    match request.segments().first() {
+        Some(&"api") => api::handle(request).await,
+        Some(&"metrics") if request.method == "GET" => Response::new(
+            200,
+            "text/plain; version=0.0.4; charset=utf-8",
+            crate::metrics::render().await.into_bytes(),
+        ),
+        _ => dashboard::handle(request).await,
+    }
+}
+
+/// Check the HTTP basic authentication credentials
+fn is_authorized(request: &Request, username: &str, password: &str) -> bool {
+    let Some(credentials) = request
+        .header("authorization")
+        .and_then(|value| value.strip_prefix("Basic "))
+        .and_then(|encoded| crate::utils::base64_decode(encoded.trim()))
+    else {
+        return false;
+    };
+    let expected = format!("{username}:{password}");
+    // compare everything to not leak the position of the first difference
+    credentials.len() == expected.len()
+        && credentials
+            .iter()
+            .zip(expected.as_bytes())
+            .fold(0u8, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+/// Read a line of at most [`MAX_LINE`] bytes, so a client cannot send an endless line
+async fn read_line<R>(reader: &mut R, line: &mut String) -> Result<usize, RequestError>
+where
+    R: tokio::io::AsyncBufRead + Unpin,
+{
+    line.clear();
+    let read = reader
+        .take(MAX_LINE as u64)
+        .read_line(line)
+        .await
+        .map_err(|_| RequestError::BadRequest)?;
+    if read == MAX_LINE && !line.ends_with('\n') {
+        return Err(RequestError::LineTooLong);
+    }
+    Ok(read)
+}
+
+async fn read_request<R>(stream: &mut R) -> Result<Request, RequestError>
+where
+    R: tokio::io::AsyncRead + Unpin,
+{
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+
+    // request line: METHOD /path?query HTTP/1.1
+    if read_line(&mut reader, &mut line).await? == 0 {
+        return Err(RequestError::Closed);
+    }
+    let mut parts = line.split_whitespace();
+    let method = parts.next().ok_or(RequestError::BadRequest)?.to_uppercase();
+    let target = parts.next().ok_or(RequestError::BadRequest)?;
+    if !parts.next().is_some_and(|v| v.starts_with("HTTP/1.")) {
+        return Err(RequestError::BadRequest);
+    }
+    let path = match target.split_once('?') {
+        Some((p, _)) => p.to_string(),
+        None => target.to_string(),
+    };
+
+    // headers
+    let mut headers = Vec::new();
+    loop {
+        if read_line(&mut reader, &mut line).await? == 0 {
+            return Err(RequestError::BadRequest);
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if headers.len() >= MAX_HEADERS {
+            return Err(RequestError::TooLarge);
+        }
+        let (name, value) = header.split_once(':').ok_or(RequestError::BadRequest)?;
+        headers.push((name.trim().to_lowercase(), value.trim().to_string()));
+    }
+
+    // body
+    let mut body = Vec::new();
+    if let Some((_, length)) = headers.iter().find(|(n, _)| n == "content-length") {
+        let length: usize = length.parse().map_err(|_| RequestError::BadRequest)?;
+        if length > MAX_BODY_SIZE {
+            return Err(RequestError::TooLarge);
+        }
+        body.resize(length, 0);
+        reader
+            .read_exact(&mut body)
+            .await
+            .map_err(|_| RequestError::BadRequest)?;
+    }
+
+    Ok(Request {
+        method,
+        path,
+        headers,
+        body,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_read_request() {
+        let mut data: &[u8] =
+            b"POST /api/torrents?x=1 HTTP/1.1\r\nHost: localhost\r\nContent-Length: 4\r\n\r\nd2:e";
+        let request = read_request(&mut data).await.unwrap();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/api/torrents");
+        assert_eq!(request.header("host"), Some("localhost"));
+        assert_eq!(request.body, b"d2:e");
+        assert_eq!(request.segments(), vec!["api", "torrents"]);
+    }
+
+    #[tokio::test]
+    async fn test_read_request_errors() {
+        let mut data: &[u8] = b"";
+        assert_eq!(
+            read_request(&mut data).await.unwrap_err(),
+            RequestError::Closed
+        );
+        let mut data: &[u8] = b"GET /\r\n\r\n";
+        assert_eq!(
+            read_request(&mut data).await.unwrap_err(),
+            RequestError::BadRequest
+        );
+        let mut data: &[u8] = b"POST / HTTP/1.1\r\nContent-Length: 999999999\r\n\r\n";
+        assert_eq!(
+            read_request(&mut data).await.unwrap_err(),
+            RequestError::TooLarge
+        );
+        let data = format!("GET / HTTP/1.1\r\nCookie: {}\r\n\r\n", "a".repeat(MAX_LINE));
+        assert_eq!(
+            read_request(&mut data.as_bytes()).await.unwrap_err(),
+            RequestError::LineTooLong
+        );
+        let data = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(MAX_LINE));
+        assert_eq!(
+            read_request(&mut data.as_bytes()).await.unwrap_err(),
+            RequestError::LineTooLong
+        );
+    }
+
+    #[test]
+    fn test_is_authorized() {
+        let mut request = Request {
+            method: String::from("GET"),
+            path: String::from("/"),
+            headers: Vec::new(),
+            body: Vec::new(),
+        };
+        assert!(!is_authorized(&request, "user", "password"));
+        request.headers.push((
+            String::from("authorization"),
+            String::from("Basic dXNlcjpwYXNzd29yZA=="),
+        ));
+        assert!(is_authorized(&request, "user", "password"));
+        assert!(!is_authorized(&request, "user", "passwore"));
+        assert!(!is_authorized(&request, "admin", "password"));
+    }
+
+    #[test]
+    fn test_response_bytes() {
+        let response = Response::json(200, String::from("{}"));
+        let bytes = String::from_utf8(response.to_bytes()).unwrap();
+        assert!(bytes.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(bytes.contains("Content-Length: 2\r\n"));
+        assert!(bytes.ends_with("\r\n\r\n{}"));
+    }
+}
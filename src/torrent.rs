@@ -1,12 +1,23 @@
 // https://wiki.theory.org/BitTorrentSpecification#Metainfo_File_Structure
 // https://wiki.theory.org/BitTorrent_Tracker_Protocol
+use std::collections::BTreeMap;
 use std::fmt;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::time::Instant;
 
-use crate::announcer::tracker::is_supported_url;
-use crate::bencode::{BencodeDecoder, BencodeDecoderError, BencodeValue, encode_bencode_value};
-use crate::utils::{get_sha1, percent_encoding};
+use encoding_rs::Encoding;
+use serde::Deserialize;
+use serde::de::IgnoredAny;
+use tracing::warn;
+use url::Url;
+
+use crate::announcer::tracker::{TrackerStatus, is_supported_url};
+use crate::bencode::{BencodeDecoderError, BencodeRef, DictRef, Limits, from_bytes_with_ref};
+use crate::config::{CategoryConfig, UnregisteredAction};
+use crate::metadata::Metadata;
+use crate::utils::{
+    base32_decode, get_sha1, get_sha256, info_hash_from_hex, percent_encoding, to_hex,
+};
 
 /// Errors that can occur when parsing a Torrent struct from Bencode.
 #[derive(Debug)]
@@ -15,20 +26,18 @@ pub enum TorrentError {
     MissingField(&'static str),
     InvalidFieldType(&'static str),
     ParseError(String), // For general parsing issues (e.g., string to u64)
-    Utf8ConversionError(&'static str),
+    Io(std::io::Error),
 }
 
 // Implement the Display trait for TorrentError
 impl fmt::Display for TorrentError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            TorrentError::BencodeError(e) => write!(f, "Bencode decoding error: {:?}", e),
+            TorrentError::BencodeError(e) => write!(f, "Bencode decoding error: {e}"),
             TorrentError::MissingField(field) => write!(f, "Missing required field: {}", field),
             TorrentError::InvalidFieldType(field) => write!(f, "Invalid type for field: {}", field),
             TorrentError::ParseError(msg) => write!(f, "Parsing error: {}", msg),
-            TorrentError::Utf8ConversionError(field) => {
-                write!(f, "UTF-8 conversion error for field: {}", field)
-            }
+            TorrentError::Io(e) => write!(f, "Cannot read file: {e}"),
         }
     }
 }
@@ -64,9 +73,12 @@ pub struct Torrent {
     pub uploaded: u64,
     /// Last announce to the tracker
     pub last_announce: std::time::Instant,
+    /// Info hash used to identify the torrent: SHA-1 for v1 and hybrid torrents, truncated SHA-256 for v2 ones
     pub info_hash: [u8; 20],
     /// URL encoded hash thet is used to build the tracker query
     pub info_hash_urlencoded: String,
+    /// SHA-256 info hash of v2 and hybrid torrents (BEP 52)
+    pub info_hash_v2: Option<[u8; 32]>,
     /// Number of seeders, it is used on the web UI
     pub seeders: u16,
     /// Number of leechers, it is used on the web UI
@@ -76,10 +88,15 @@ pub struct Torrent {
     /// Current interval after the last annouce
     pub interval: u64,
     pub error_count: u16,
-    // pub creation_date: Option<DateTime<Local>>,
-    // pub comment: Option<String>,
-    // pub created_by: Option<String>,
+    /// Error of the last announce to one of the trackers, `None` if the last announce succeeded
+    pub last_error: Option<String>,
+    /// Result of the last announce to each tracker, by URL
+    pub trackers: BTreeMap<String, TrackerStatus>,
+    /// A paused torrent is not announced until it is resumed
+    pub paused: bool,
     pub encoding: Option<String>,
+    /// Optional fields of the torrent file (creation date, comment, files...), not used to announce
+    pub metadata: Option<Box<Metadata>>,
 
     // for tracker response
     /// (optional) Minimum announce interval. If present clients must not reannounce more frequently than this.
@@ -89,12 +106,118 @@ pub struct Torrent {
 
     /// Source file path (used for file watcher to identify torrents on removal)
     pub source_path: Option<PathBuf>,
+    /// Name of the first subfolder of the torrent directory where the file is, if any
+    pub category: Option<String>,
+}
+
+/// Extension of bencoded torrent files
+pub const TORRENT_EXTENSION: &str = "torrent";
+/// Extension of text files with one magnet link per line
+pub const MAGNET_EXTENSION: &str = "magnet";
+
+/// Tells if the file can be loaded from the torrent directory (`.torrent` or `.magnet`)
+pub fn is_torrent_file(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| {
+        ext.eq_ignore_ascii_case(TORRENT_EXTENSION) || ext.eq_ignore_ascii_case(MAGNET_EXTENSION)
+    })
+}
+
+/// Load the torrent(s) of a `.torrent` file or of a `.magnet` file, that may contain several magnet links.
+///
+/// Each magnet link is parsed on its own so an invalid line does not prevent loading the other ones.
+pub fn load_file(path: &Path) -> Vec<Result<Torrent, TorrentError>> {
+    let mut results = if !path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case(MAGNET_EXTENSION))
+    {
+        vec![Torrent::from_file(path.to_path_buf())]
+    } else {
+        let content = match std::fs::read_to_string(path) {
+            Ok(c) => c,
+            Err(e) => return vec![Err(TorrentError::Io(e))],
+        };
+        content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| {
+                let mut torrent = Torrent::from_magnet(line)?;
+                torrent.source_path = Some(path.to_path_buf());
+                Ok(torrent)
+            })
+            .collect()
+    };
+    // the trackers that do not know a torrent are not announced again after a restart
+    let unregistered = read_unregistered(path);
+    if !unregistered.is_empty() {
+        for torrent in results.iter_mut().flatten() {
+            torrent.restore_unregistered(&unregistered);
+        }
+    }
+    results
+}
+
+/// Extension of the marker file that keeps a torrent paused, i.e. `name.torrent.paused`
+pub const PAUSED_EXTENSION: &str = "paused";
+
+/// Path of the marker file that keeps the given torrent file paused
+pub fn paused_marker(torrent_path: &std::path::Path) -> PathBuf {
+    let mut marker = torrent_path.as_os_str().to_owned();
+    marker.push(".");
+    marker.push(PAUSED_EXTENSION);
+    PathBuf::from(marker)
+}
+
+/// Extension of the file with the trackers on which the torrents of a file are not registered, i.e.
+/// `name.torrent.unregistered`. Each line has the info hash, the tracker URL and its failure reason.
+pub const UNREGISTERED_EXTENSION: &str = "unregistered";
+
+/// Path of the file with the trackers on which the torrents of the given file are not registered
+pub fn unregistered_marker(torrent_path: &Path) -> PathBuf {
+    let mut marker = torrent_path.as_os_str().to_owned();
+    marker.push(".");
+    marker.push(UNREGISTERED_EXTENSION);
+    PathBuf::from(marker)
+}
+
+/// Info hash, tracker URL and failure reason of the trackers on which the torrents of a file are not registered
+fn read_unregistered(torrent_path: &Path) -> Vec<(String, String, String)> {
+    let Ok(content) = std::fs::read_to_string(unregistered_marker(torrent_path)) else {
+        return Vec::new();
+    };
+    content
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.splitn(3, ' ');
+            let info_hash = parts.next()?.to_string();
+            let url = parts.next()?.to_string();
+            Some((info_hash, url, parts.next().unwrap_or_default().to_string()))
+        })
+        .collect()
 }
 
 impl Torrent {
     /// Tells if we can announce to tracker(s) depending on the last announce
     pub fn should_announce(&self) -> bool {
-        self.last_announce.elapsed().as_secs() >= self.interval
+        !self.paused && self.last_announce.elapsed().as_secs() >= self.interval
+    }
+
+    /// Tells if the tracker allows a new announce, according to the `min interval` it sent
+    pub fn can_reannounce(&self) -> bool {
+        self.min_interval
+            .is_none_or(|min| self.last_announce.elapsed().as_secs() >= min)
+    }
+
+    /// Hashes to announce: the info hash, and the truncated v2 hash of hybrid torrents (BEP 52)
+    pub fn announce_hashes(&self) -> Vec<[u8; 20]> {
+        let mut hashes = vec![self.info_hash];
+        if let Some(v2) = &self.info_hash_v2 {
+            let truncated = truncate_v2_hash(v2);
+            if truncated != self.info_hash {
+                hashes.push(truncated);
+            }
+        }
+        hashes
     }
 
     /// Tells if we can upload (need leechers)
@@ -111,9 +234,93 @@ impl Torrent {
         }
     }
 
-    pub fn compute_speeds(&mut self) {
-        let config = crate::CONFIG.get().unwrap();
-        self.uploaded(config.min_upload_rate, config.max_upload_rate);
+    pub async fn compute_speeds(&mut self) {
+        let mut rates = *crate::RATES.read().await;
+        if let Some(category) = self.category_config() {
+            rates = category.rates(rates);
+        }
+        self.uploaded(rates.min_upload_rate, rates.max_upload_rate);
+    }
+
+    /// Settings of the category of the torrent, if any
+    pub fn category_config(&self) -> Option<&'static CategoryConfig> {
+        crate::CONFIG.get()?.category(self.category.as_deref())
+    }
+
+    /// Tells if the torrent must be stopped because it is not registered: on one of its trackers with
+    /// `unregistered_action = "stop_torrent"`, on all of them otherwise
+    pub fn is_unregistered(&self) -> bool {
+        let mut unregistered = self
+            .urls
+            .iter()
+            .map(|url| self.trackers.get(url).is_some_and(|s| s.unregistered));
+        match crate::CONFIG.get().map(|c| c.unregistered_action) {
+            Some(UnregisteredAction::StopTorrent) => unregistered.any(|u| u),
+            _ => !self.urls.is_empty() && unregistered.all(|u| u),
+        }
+    }
+
+    /// Failure reasons of the trackers on which the torrent is not registered, one line per tracker
+    pub fn unregistered_reason(&self) -> String {
+        let lines: Vec<String> = self
+            .trackers
+            .iter()
+            .filter(|(_, status)| status.unregistered)
+            .map(|(url, status)| format!("{url}: {}", status.last_error.as_deref().unwrap_or("")))
+            .collect();
+        lines.join("\n")
+    }
+
+    /// Save the trackers on which the torrent is not registered next to its file, they are not announced again after a
+    /// restart. The lines of the other torrents of the file (magnet links) are kept.
+    pub fn save_unregistered(&self) {
+        let Some(path) = &self.source_path else {
+            return;
+        };
+        let info_hash = to_hex(&self.info_hash);
+        let mut lines: Vec<String> = read_unregistered(path)
+            .into_iter()
+            .filter(|(hash, ..)| *hash != info_hash)
+            .map(|(hash, url, reason)| format!("{hash} {url} {reason}"))
+            .collect();
+        for (url, status) in self.trackers.iter().filter(|(_, s)| s.unregistered) {
+            let reason = status.last_error.as_deref().unwrap_or_default();
+            lines.push(format!("{info_hash} {url} {}", reason.replace('\n', " ")));
+        }
+        let marker = unregistered_marker(path);
+        let result = if lines.is_empty() {
+            std::fs::remove_file(&marker)
+        } else {
+            std::fs::write(&marker, lines.join("\n") + "\n")
+        };
+        if let Err(e) = result
+            && e.kind() != std::io::ErrorKind::NotFound
+        {
+            warn!("Cannot update {}: {e}", marker.display());
+        }
+    }
+
+    /// Mark the trackers on which the torrent is not registered, from the lines of [`read_unregistered`]
+    fn restore_unregistered(&mut self, unregistered: &[(String, String, String)]) {
+        let info_hash = to_hex(&self.info_hash);
+        for (_, url, reason) in unregistered.iter().filter(|(hash, ..)| *hash == info_hash) {
+            if self.urls.contains(url) {
+                self.trackers.insert(
+                    url.clone(),
+                    TrackerStatus {
+                        last_announce: None,
+                        last_error: Some(reason.clone()),
+                        unregistered: true,
+                    },
+                );
+            }
+        }
+    }
+
+    /// Tells if a stop rule of the category is reached, then the torrent should be paused
+    pub fn should_stop(&self) -> bool {
+        self.category_config()
+            .is_some_and(|c| c.should_stop(self.uploaded, self.length))
     }
 
     // /// Load essential data from a parsed torrent using the full parsed torrent file. It reduces the RAM use to have smaller data
@@ -150,40 +357,89 @@ impl Torrent {
     // }
 
     pub fn from_file(path: PathBuf) -> Result<Self, TorrentError> {
-        let data = std::fs::read(&path).expect("Cannot read torrent file");
+        // do not read a huge file that the decoder would reject
+        let max_size = bencode_limits().max_size;
+        if std::fs::metadata(&path).map_err(TorrentError::Io)?.len() > max_size as u64 {
+            return Err(TorrentError::ParseError(format!(
+                "file is larger than {max_size} bytes"
+            )));
+        }
+        let data = std::fs::read(&path).map_err(TorrentError::Io)?;
         let mut torrent = Self::from_bencode_bytes(&data)?;
         torrent.source_path = Some(path);
         Ok(torrent)
     }
 
-    pub fn to_json(&self) -> String {
-        let mut result = String::with_capacity(256);
-        result.push_str("\t{\"name\": \"");
-        result.push_str(&self.name.replace("\"", "\\\""));
-        result.push_str("\", \"length\": ");
-        result.push_str(&self.length.to_string());
-        result.push_str(", \"private\": ");
-        result.push_str(&self.private.to_string());
-        result.push_str(", \"uploaded\": ");
-        result.push_str(&self.uploaded.to_string());
-        result.push_str(", \"seeders\": ");
-        result.push_str(&self.seeders.to_string());
-        result.push_str(", \"leechers\": ");
-        result.push_str(&self.leechers.to_string());
-        result.push_str(", \"next_upload_speed\": ");
-        result.push_str(&self.next_upload_speed.to_string());
-        result.push_str(", \"urls\": [");
-        let count = self.urls.len();
-        for (index, url) in self.urls.iter().enumerate() {
-            result.push_str(&format!("\"{url}\""));
-            if (index + 1) < count {
-                result.push_str(", ");
-            } else {
-                result.push_str("]}\n");
+    /// Build a torrent from a magnet link, i.e. `magnet:?xt=urn:btih:<hash>&dn=<name>&xl=<length>&tr=<tracker>`.
+    ///
+    /// The info hash is enough to announce, the name and the length are optional. The hash can be in hexadecimal
+    /// (40 characters) or in base32 (32 characters).
+    pub fn from_magnet(uri: &str) -> Result<Self, TorrentError> {
+        let url = Url::parse(uri).map_err(|e| TorrentError::ParseError(e.to_string()))?;
+        if url.scheme() != "magnet" {
+            return Err(TorrentError::ParseError(String::from("not a magnet link")));
+        }
+
+        let mut info_hash: Option<[u8; 20]> = None;
+        let mut info_hash_v2: Option<[u8; 32]> = None;
+        let mut name: Option<String> = None;
+        let mut length: u64 = 0;
+        let mut urls: Vec<String> = Vec::new();
+        for (key, value) in url.query_pairs() {
+            match key.as_ref() {
+                "xt" => {
+                    if let Some(hash) = value.strip_prefix("urn:btih:") {
+                        info_hash.get_or_insert(parse_magnet_hash(hash)?);
+                    } else if let Some(hash) = value.strip_prefix("urn:btmh:") {
+                        info_hash_v2.get_or_insert(parse_magnet_multihash(hash)?);
+                    }
+                }
+                "dn" => name = Some(value.into_owned()),
+                "xl" => {
+                    length = value
+                        .parse()
+                        .map_err(|_| TorrentError::InvalidFieldType("xl"))?
+                }
+                "tr" if !urls.contains(&value.to_string()) && is_supported_url(&value) => {
+                    urls.push(value.into_owned());
+                }
+                _ => {}
             }
         }
-        // TODO: add info hash?
-        result
+
+        // a v2 only link is announced with the truncated v2 hash
+        let info_hash = info_hash
+            .or(info_hash_v2.map(|h| truncate_v2_hash(&h)))
+            .ok_or(TorrentError::MissingField("xt=urn:btih"))?;
+        if urls.is_empty() {
+            return Err(TorrentError::MissingField("tr"));
+        }
+
+        Ok(Torrent {
+            name: name.unwrap_or_else(|| to_hex(&info_hash)),
+            urls,
+            length,
+            private: false,
+            uploaded: 0,
+            last_announce: Instant::now(),
+            info_hash,
+            info_hash_urlencoded: percent_encoding(&info_hash),
+            info_hash_v2,
+            seeders: 0,
+            leechers: 0,
+            next_upload_speed: 0,
+            interval: 0,
+            error_count: 0,
+            last_error: None,
+            trackers: BTreeMap::new(),
+            paused: false,
+            encoding: None,
+            min_interval: None,
+            tracker_id: None,
+            source_path: None,
+            category: None,
+            metadata: None,
+        })
     }
 
     /// Parses a raw bencoded .torrent file byte slice into a Torrent struct.
@@ -197,9 +453,13 @@ impl Torrent {
     /// # Returns
     /// A `Result` which is `Ok(Torrent)` on success or `Err(TorrentError)` on failure.
     pub fn from_bencode_bytes(bencode_data: &[u8]) -> Result<Self, TorrentError> {
-        let mut decoder = BencodeDecoder::new(bencode_data);
-        let top_level_dict = match decoder.decode()? {
-            BencodeValue::Dictionary(dict) => dict,
+        // byte strings are borrowed, i.e. `pieces` is never copied. The dictionaries are still needed for the info
+        // hash, the v2 file tree and the metadata.
+        let (meta_info, root): (MetaInfo, BencodeRef) =
+            from_bytes_with_ref(bencode_data, bencode_limits())?;
+        let info = meta_info.info;
+        let top_level_dict = match root {
+            BencodeRef::Dictionary(dict) => dict,
             _ => {
                 return Err(TorrentError::InvalidFieldType(
                     "Top-level is not a dictionary",
@@ -209,23 +469,13 @@ impl Torrent {
 
         // --- Extract announce URLs ---
         let mut urls = Vec::new();
-        // Try to get 'announce-list' first (multi-tracker)
-        if let Some(BencodeValue::List(announce_list_bencode)) =
-            top_level_dict.get(b"announce-list".as_ref())
-        {
-            for tier in announce_list_bencode {
-                if let BencodeValue::List(tier_urls) = tier {
-                    for url_bencode in tier_urls {
-                        if let BencodeValue::ByteString(url_bytes) = url_bencode {
-                            let url_str = std::str::from_utf8(url_bytes)
-                                .map_err(|_| {
-                                    TorrentError::Utf8ConversionError("announce-list URL")
-                                })?
-                                .to_string();
-                            if !urls.contains(&url_str) && is_supported_url(&url_str) {
-                                // Avoid duplicates
-                                urls.push(url_str);
-                            }
+        // Try to get 'announce-list' first (multi-tracker), invalid tiers and URLs are skipped
+        if let Some(Lenient::Valid(tiers)) = meta_info.announce_list {
+            for tier in tiers {
+                if let Lenient::Valid(tier_urls) = tier {
+                    for url in tier_urls {
+                        if let Lenient::Valid(url_bytes) = url {
+                            push_url(&mut urls, url_bytes);
                         }
                     }
                 }
@@ -233,138 +483,275 @@ impl Torrent {
         }
 
         // Try to get 'announce' (single tracker), add if not already in urls
-        if let Some(BencodeValue::ByteString(announce_bytes)) =
-            top_level_dict.get(b"announce".as_ref())
-        {
-            let announce_str = std::str::from_utf8(announce_bytes)
-                .map_err(|_| TorrentError::Utf8ConversionError("announce URL"))?
-                .to_string();
-            if !urls.contains(&announce_str) && is_supported_url(&announce_str) {
-                // Avoid duplicates
-                urls.push(announce_str);
-            }
+        if let Some(Lenient::Valid(announce_bytes)) = meta_info.announce {
+            push_url(&mut urls, announce_bytes);
         }
 
         if urls.is_empty() {
             return Err(TorrentError::MissingField("announce or announce-list"));
         }
 
-        // --- Extract 'info' dictionary and calculate info_hash ---
-        // `info_bytes_slice` is `&BencodeValue`
-        let info_bytes_slice = top_level_dict
-            .get(b"info".as_ref())
+        // --- Calculate info_hash ---
+        // the info hash is computed over the original bytes, the dictionary may not be canonical
+        let info_bencoded_raw = top_level_dict
+            .get_raw(b"info")
             .ok_or(TorrentError::MissingField("info"))?;
-
-        // Ensure info_bytes_slice is indeed a dictionary before proceeding
-        let info_dict_map = match info_bytes_slice {
-            BencodeValue::Dictionary(dict) => dict, // `dict` here is `&BTreeMap`
-            _ => return Err(TorrentError::InvalidFieldType("info is not a dictionary")),
+        let Some(BencodeRef::Dictionary(info_dict_map)) = top_level_dict.get(b"info") else {
+            return Err(TorrentError::InvalidFieldType("info is not a dictionary"));
         };
 
-        let mut encoder_buf = Vec::new();
-        // Pass the reference to the info dictionary directly to the encoder.
-        // `info_bytes_slice` is already `&BencodeValue`.
-        encode_bencode_value(info_bytes_slice, &mut encoder_buf)?;
-        let info_bencoded_raw = encoder_buf;
-
-        let info_hash: [u8; 20] = get_sha1(&info_bencoded_raw);
+        // BEP 52: v2 torrents have `meta version` 2, hybrid ones also have the v1 `pieces`
+        let is_v2 = info.meta_version == Some(2);
+        let is_v1 = info.pieces.is_some();
+        let info_hash_v2 = is_v2.then(|| get_sha256(info_bencoded_raw));
+        let info_hash: [u8; 20] = match info_hash_v2 {
+            Some(hash) if !is_v1 => truncate_v2_hash(&hash),
+            _ => get_sha1(info_bencoded_raw),
+        };
         let info_hash_urlencoded = percent_encoding(&info_hash);
 
         // --- Decode 'info' dictionary content ---
-        // `info_dict_map` is already `&BTreeMap` from the match above, so we can use it directly.
-
-        let name_bytes = info_dict_map
-            .get(b"name".as_ref())
-            .ok_or(TorrentError::MissingField("info.name"))?;
-        let name = match name_bytes {
-            BencodeValue::ByteString(b) => std::str::from_utf8(b)
-                .map_err(|_| TorrentError::Utf8ConversionError("info.name"))?
-                .to_string(),
-            _ => return Err(TorrentError::InvalidFieldType("info.name")),
+
+        // Older clients write the name in the `encoding` of the torrent, and may give a UTF-8 one in `name.utf-8`
+        let text_encoding = text_encoding(top_level_dict);
+        let name = match info.name_utf8.map(std::str::from_utf8) {
+            Some(Ok(name)) => name.to_string(),
+            _ => decode_text(info.name, text_encoding),
         };
 
-        let mut total_length: u64 = 0;
-        let mut is_private = false;
-        let mut encoding_option: Option<String> = None;
+        // 'length' for single-file torrents, the sum of 'files' for multi-file torrents
+        let mut total_length = match info.files {
+            Some(files) => files
+                .iter()
+                .try_fold(0u64, |total, file| total.checked_add(file.length))
+                .ok_or_else(length_overflow)?,
+            None => info.length.unwrap_or(0),
+        };
 
-        // Handle 'length' for single-file torrents
-        if let Some(BencodeValue::Integer(len)) = info_dict_map.get(b"length".as_ref()) {
-            if *len < 0 {
-                return Err(TorrentError::ParseError(
-                    "info.length is negative".to_string(),
-                ));
-            }
-            total_length = *len as u64;
+        // Handle 'file tree' for v2 torrents, v1 files of hybrid torrents may have padding files
+        if is_v2 {
+            let file_tree = match info_dict_map.get(b"file tree") {
+                Some(BencodeRef::Dictionary(tree)) => tree,
+                Some(_) => return Err(TorrentError::InvalidFieldType("info.file tree")),
+                None => return Err(TorrentError::MissingField("info.file tree")),
+            };
+            total_length = file_tree_length(file_tree)?;
         }
 
-        // Handle 'files' for multi-file torrents
-        if let Some(BencodeValue::List(files)) = info_dict_map.get(b"files".as_ref()) {
-            total_length = 0; // Reset if 'files' is present, sum up
-            for file_entry in files {
-                if let BencodeValue::Dictionary(file_dict) = file_entry {
-                    if let Some(BencodeValue::Integer(file_len)) = file_dict.get(b"length".as_ref())
-                    {
-                        if *file_len < 0 {
-                            return Err(TorrentError::ParseError(
-                                "file.length is negative".to_string(),
-                            ));
-                        }
-                        total_length += *file_len as u64;
-                    } else {
-                        return Err(TorrentError::MissingField(
-                            "file.length in multi-file torrent",
-                        ));
-                    }
-                } else {
-                    return Err(TorrentError::InvalidFieldType(
-                        "file entry in multi-file torrent",
-                    ));
-                }
+        let encoding_option = match meta_info.encoding {
+            Some(Lenient::Valid(encoding_bytes)) => {
+                Some(String::from_utf8_lossy(encoding_bytes).into_owned())
             }
-        }
-
-        // Handle 'private' flag
-        if let Some(BencodeValue::Integer(private_val)) = info_dict_map.get(b"private".as_ref()) {
-            is_private = *private_val == 1;
-        }
+            _ => None,
+        };
 
-        // Handle 'encoding'
-        if let Some(BencodeValue::ByteString(encoding_bytes)) =
-            top_level_dict.get(b"encoding".as_ref())
-        {
-            encoding_option = Some(
-                std::str::from_utf8(encoding_bytes)
-                    .map_err(|_| TorrentError::Utf8ConversionError("encoding"))?
-                    .to_string(),
-            );
-        }
+        // only kept in RAM for the stats, `inspect` and the API read it from the file
+        let metadata = crate::CONFIG.get().filter(|c| c.stats_metadata).map(|_| {
+            Box::new(Metadata::from_dictionaries(
+                top_level_dict,
+                info_dict_map,
+                &name,
+            ))
+        });
 
         Ok(Torrent {
             name,
             urls,
             length: total_length,
-            private: is_private,
+            private: info.private == Some(1),
             uploaded: 0,                   // Default value
             last_announce: Instant::now(), // Default value
             info_hash,
             info_hash_urlencoded,
+            info_hash_v2,
             seeders: 0,           // Default value
             leechers: 0,          // Default value
             next_upload_speed: 0, // Default value
             interval: 0,          // Default value
             error_count: 0,       // Default value
+            last_error: None,     // Default value
+            trackers: BTreeMap::new(),
+            paused: false, // Default value
             encoding: encoding_option,
             min_interval: None, // Default value (from tracker response, not torrent file)
             tracker_id: None,   // Default value (from tracker response, not torrent file)
             source_path: None,  // Set by from_file() if loaded from disk
+            category: None,     // Set by the loader from the source path
+            metadata,
         })
     }
 }
 
+/// Fields of a torrent file needed to announce, the other ones are read by [`Metadata`]
+#[derive(Deserialize)]
+struct MetaInfo<'a> {
+    #[serde(borrow)]
+    announce: Option<Lenient<&'a [u8]>>,
+    #[serde(rename = "announce-list", borrow)]
+    announce_list: Option<Lenient<Vec<Tier<'a>>>>,
+    #[serde(borrow)]
+    encoding: Option<Lenient<&'a [u8]>>,
+    #[serde(borrow)]
+    info: Info<'a>,
+}
+
+/// A tier of `announce-list`, a list of URLs
+type Tier<'a> = Lenient<Vec<Lenient<&'a [u8]>>>;
+
+#[derive(Deserialize)]
+struct Info<'a> {
+    name: &'a [u8],
+    #[serde(rename = "name.utf-8")]
+    name_utf8: Option<&'a [u8]>,
+    /// Single file torrents
+    length: Option<u64>,
+    /// Multi-file torrents
+    files: Option<Vec<FileEntry>>,
+    #[serde(rename = "meta version")]
+    meta_version: Option<i64>,
+    /// v1 piece hashes, not read
+    pieces: Option<IgnoredAny>,
+    private: Option<i64>,
+}
+
+#[derive(Deserialize)]
+struct FileEntry {
+    length: u64,
+}
+
+/// A value of the expected type, or an invalid one that is ignored
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Lenient<T> {
+    Valid(T),
+    Invalid(IgnoredAny),
+}
+
+/// Limits of the decoder, from the config
+pub fn bencode_limits() -> Limits {
+    crate::CONFIG
+        .get()
+        .map(|c| c.bencode_limits)
+        .unwrap_or_default()
+}
+
+/// Add an announce URL if it is supported and not a duplicate, a URL that is not UTF-8 is skipped with a warning
+fn push_url(urls: &mut Vec<String>, url_bytes: &[u8]) {
+    match std::str::from_utf8(url_bytes) {
+        Ok(url) => {
+            if !urls.iter().any(|u| u == url) && is_supported_url(url) {
+                urls.push(url.to_string());
+            }
+        }
+        Err(_) => warn!(
+            "Skipping announce URL that is not valid UTF-8: {}",
+            String::from_utf8_lossy(url_bytes)
+        ),
+    }
+}
+
+/// Text encoding of the strings of a torrent file, from its `encoding` field (i.e. `GBK` or `Shift_JIS`)
+pub fn text_encoding(root: DictRef) -> Option<&'static Encoding> {
+    match root.get(b"encoding") {
+        Some(BencodeRef::ByteString(label)) => Encoding::for_label(label),
+        _ => None,
+    }
+}
+
+/// Decode a string of a torrent file: UTF-8 if it is valid, else with the encoding of the torrent, else lossy UTF-8
+pub fn decode_text(bytes: &[u8], encoding: Option<&'static Encoding>) -> String {
+    match (std::str::from_utf8(bytes), encoding) {
+        (Ok(text), _) => text.to_string(),
+        (Err(_), Some(encoding)) => encoding.decode_without_bom_handling(bytes).0.into_owned(),
+        (Err(_), None) => String::from_utf8_lossy(bytes).into_owned(),
+    }
+}
+
+/// Get a string of a dictionary, its `<key>.utf-8` variant is preferred if it is valid UTF-8
+pub fn get_text(dict: DictRef, key: &str, encoding: Option<&'static Encoding>) -> Option<String> {
+    if let Some(BencodeRef::ByteString(b)) = dict.get(format!("{key}.utf-8").as_bytes())
+        && let Ok(text) = std::str::from_utf8(b)
+    {
+        return Some(text.to_string());
+    }
+    match dict.get(key.as_bytes()) {
+        Some(BencodeRef::ByteString(b)) => Some(decode_text(b, encoding)),
+        _ => None,
+    }
+}
+
+/// v2 info hashes are truncated to 20 bytes for tracker announces (BEP 52)
+pub fn truncate_v2_hash(hash: &[u8; 32]) -> [u8; 20] {
+    let mut truncated = [0u8; 20];
+    truncated.copy_from_slice(&hash[..20]);
+    truncated
+}
+
+/// Sum the file lengths of a v2 `file tree`: folders and files are dictionaries, a file has an empty key with its
+/// `length`
+fn file_tree_length(tree: DictRef) -> Result<u64, TorrentError> {
+    let mut total: u64 = 0;
+    for (key, value) in tree.iter() {
+        let BencodeRef::Dictionary(node) = value else {
+            return Err(TorrentError::InvalidFieldType("info.file tree"));
+        };
+        if key.is_empty() {
+            match node.get(b"length") {
+                Some(BencodeRef::Integer(len)) if len >= 0 => {
+                    total = total.checked_add(len as u64).ok_or_else(length_overflow)?;
+                }
+                Some(_) => {
+                    return Err(TorrentError::ParseError(
+                        "file tree length is negative or not an integer".to_string(),
+                    ));
+                }
+                None => return Err(TorrentError::MissingField("file tree length")),
+            }
+        } else {
+            total = total
+                .checked_add(file_tree_length(node)?)
+                .ok_or_else(length_overflow)?;
+        }
+    }
+    Ok(total)
+}
+
+/// The sum of the file lengths does not fit in a `u64`
+fn length_overflow() -> TorrentError {
+    TorrentError::ParseError("total length of the files overflows".to_string())
+}
+
+/// Parse a v2 info hash of a magnet link: a SHA-256 multihash (`1220` followed by the hash) in hexadecimal
+fn parse_magnet_multihash(hash: &str) -> Result<[u8; 32], TorrentError> {
+    let hex = hash
+        .strip_prefix("1220")
+        .filter(|h| h.len() == 64 && h.is_ascii())
+        .ok_or(TorrentError::InvalidFieldType("xt=urn:btmh"))?;
+    let mut result = [0u8; 32];
+    for (i, byte) in result.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)
+            .map_err(|_| TorrentError::InvalidFieldType("xt=urn:btmh"))?;
+    }
+    Ok(result)
+}
+
+/// Parse the info hash of a magnet link, in hexadecimal or base32
+fn parse_magnet_hash(hash: &str) -> Result<[u8; 20], TorrentError> {
+    let bytes = match hash.len() {
+        40 => info_hash_from_hex(hash).map(Vec::from),
+        32 => base32_decode(hash),
+        _ => None,
+    };
+    bytes
+        .and_then(|b| b.try_into().ok())
+        .ok_or(TorrentError::InvalidFieldType("xt=urn:btih"))
+}
+
 // TODO: test tracker response "with d8:completei0e10:downloadedi0e10:incompletei1e8:intervali1922e12:min intervali961e5:peers6:<3A><><EFBFBD>m<EFBFBD><6D>e"
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::bencode::{BencodeValue, encode_bencode_value};
 
     #[test]
     fn test_can_download_or_upload() {
@@ -376,16 +763,22 @@ mod tests {
             last_announce: std::time::Instant::now(),
             info_hash: [0; 20],
             info_hash_urlencoded: String::from("01234567"),
+            info_hash_v2: None,
             seeders: 0,
             leechers: 1,
             next_upload_speed: 0,
             interval: 1800,
             urls: Vec::with_capacity(0),
             error_count: 0,
+            last_error: None,
+            trackers: BTreeMap::new(),
+            paused: false,
             encoding: None,
             min_interval: None,
             tracker_id: None,
             source_path: None,
+            category: None,
+            metadata: None,
         };
         assert!(!t.can_upload());
         t.leechers = 5;
@@ -408,16 +801,22 @@ mod tests {
             last_announce: std::time::Instant::now(),
             info_hash: [0; 20],
             info_hash_urlencoded: String::from("01234567"),
+            info_hash_v2: None,
             seeders: 4,
             leechers: 16,
             next_upload_speed: 0,
             interval: 1800,
             urls: Vec::with_capacity(0),
             error_count: 0,
+            last_error: None,
+            trackers: BTreeMap::new(),
+            paused: false,
             encoding: None,
             min_interval: None,
             tracker_id: None,
             source_path: None,
+            category: None,
+            metadata: None,
         };
         let speed = t.uploaded(16, 64);
         assert!(speed > 0);
@@ -427,4 +826,302 @@ mod tests {
         let speed = t.uploaded(16, 64);
         assert!((16..=64).contains(&speed));
     }
+
+    #[test]
+    fn test_paused_marker() {
+        assert_eq!(
+            paused_marker(std::path::Path::new("/tmp/name.torrent")),
+            PathBuf::from("/tmp/name.torrent.paused")
+        );
+    }
+
+    #[test]
+    fn test_save_unregistered() {
+        let dir = std::env::temp_dir().join("ratioup-test-unregistered");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("a.torrent");
+        std::fs::copy("tests/Audio_20160422_archive.torrent", &path).unwrap();
+        let mut t = Torrent::from_file(path.clone()).unwrap();
+        let url = t.urls[0].clone();
+        t.trackers.insert(
+            url.clone(),
+            TrackerStatus {
+                last_announce: None,
+                last_error: Some(String::from("tracker error: unregistered torrent")),
+                unregistered: true,
+            },
+        );
+        t.save_unregistered();
+        assert_eq!(
+            t.unregistered_reason(),
+            format!("{url}: tracker error: unregistered torrent")
+        );
+
+        // loaded again, the tracker is still disabled
+        let loaded = load_file(&path).remove(0).unwrap();
+        assert!(loaded.trackers.get(&url).is_some_and(|s| s.unregistered));
+        assert_eq!(loaded.unregistered_reason(), t.unregistered_reason());
+
+        // resumed
+        t.trackers.clear();
+        t.save_unregistered();
+        assert!(!unregistered_marker(&path).exists());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_should_announce_and_reannounce() {
+        let mut t = Torrent::from_file(PathBuf::from("tests/Audio_20160422_archive.torrent"))
+            .expect("Cannot parse test torrent");
+        assert!(t.should_announce());
+        assert!(t.can_reannounce());
+        t.paused = true;
+        assert!(!t.should_announce());
+        t.min_interval = Some(900);
+        assert!(!t.can_reannounce());
+    }
+
+    #[test]
+    fn test_from_magnet() {
+        let t = Torrent::from_magnet(
+            "magnet:?xt=urn:btih:0123456789ABCDEF0123456789abcdef01234567&dn=Some+name%21&xl=1024\
+            &tr=udp%3A%2F%2Ftracker.tld%3A6969%2Fannounce&tr=udp%3A%2F%2Ftracker.tld%3A6969%2Fannounce\
+            &tr=wss%3A%2F%2Fnot.supported",
+        )
+        .unwrap();
+        assert_eq!(t.name, "Some name!");
+        assert_eq!(t.length, 1024);
+        assert_eq!(
+            t.urls,
+            vec![String::from("udp://tracker.tld:6969/announce")]
+        );
+        assert_eq!(
+            to_hex(&t.info_hash),
+            "0123456789abcdef0123456789abcdef01234567"
+        );
+        assert_eq!(t.info_hash_urlencoded, percent_encoding(&t.info_hash));
+
+        // base32 hash and no name
+        let t = Torrent::from_magnet(
+            "magnet:?xt=urn:btih:AERUKZ4JVPG66AJDIVTYTK6N54ASGRLH&tr=http://tracker.tld/announce",
+        )
+        .unwrap();
+        assert_eq!(t.name, "0123456789abcdef0123456789abcdef01234567");
+        assert_eq!(t.length, 0);
+
+        assert!(matches!(
+            Torrent::from_magnet("magnet:?dn=name&tr=http://tracker.tld/announce"),
+            Err(TorrentError::MissingField(_))
+        ));
+        assert!(matches!(
+            Torrent::from_magnet("magnet:?xt=urn:btih:0123"),
+            Err(TorrentError::InvalidFieldType(_))
+        ));
+        assert!(
+            Torrent::from_magnet(
+                "http://tracker.tld/?xt=urn:btih:0123456789abcdef0123456789abcdef01234567"
+            )
+            .is_err()
+        );
+    }
+
+    /// Build a torrent file with the given info dictionary
+    fn torrent_bytes(info: BTreeMap<Vec<u8>, BencodeValue>) -> (Vec<u8>, Vec<u8>) {
+        let info = BencodeValue::Dictionary(info);
+        let mut info_bytes = Vec::new();
+        encode_bencode_value(&info, &mut info_bytes).unwrap();
+        let mut root = BTreeMap::new();
+        root.insert(
+            b"announce".to_vec(),
+            BencodeValue::ByteString(b"http://tracker.tld/announce".to_vec()),
+        );
+        root.insert(b"info".to_vec(), info);
+        let mut bytes = Vec::new();
+        encode_bencode_value(&BencodeValue::Dictionary(root), &mut bytes).unwrap();
+        (bytes, info_bytes)
+    }
+
+    /// v2 info dictionary with `a.txt` (100 bytes) and `folder/b.txt` (50 bytes)
+    fn v2_info() -> BTreeMap<Vec<u8>, BencodeValue> {
+        let file = |length: i64| {
+            let mut attributes = BTreeMap::new();
+            attributes.insert(b"length".to_vec(), BencodeValue::Integer(length));
+            let mut node = BTreeMap::new();
+            node.insert(Vec::new(), BencodeValue::Dictionary(attributes));
+            BencodeValue::Dictionary(node)
+        };
+        let mut folder = BTreeMap::new();
+        folder.insert(b"b.txt".to_vec(), file(50));
+        let mut tree = BTreeMap::new();
+        tree.insert(b"a.txt".to_vec(), file(100));
+        tree.insert(b"folder".to_vec(), BencodeValue::Dictionary(folder));
+        let mut info = BTreeMap::new();
+        info.insert(b"file tree".to_vec(), BencodeValue::Dictionary(tree));
+        info.insert(b"meta version".to_vec(), BencodeValue::Integer(2));
+        info.insert(b"name".to_vec(), BencodeValue::ByteString(b"v2".to_vec()));
+        info.insert(b"piece length".to_vec(), BencodeValue::Integer(16384));
+        info
+    }
+
+    #[test]
+    fn test_v2_and_hybrid() {
+        let (bytes, info_bytes) = torrent_bytes(v2_info());
+        let t = Torrent::from_bencode_bytes(&bytes).unwrap();
+        let v2 = get_sha256(&info_bytes);
+        assert_eq!(t.length, 150);
+        assert_eq!(t.info_hash_v2, Some(v2));
+        assert_eq!(t.info_hash, truncate_v2_hash(&v2));
+        assert_eq!(t.announce_hashes(), vec![truncate_v2_hash(&v2)]);
+
+        // hybrid: v1 fields with a padding file are ignored for the length
+        let mut info = v2_info();
+        info.insert(b"pieces".to_vec(), BencodeValue::ByteString(vec![0; 20]));
+        info.insert(b"length".to_vec(), BencodeValue::Integer(16384));
+        let (bytes, info_bytes) = torrent_bytes(info);
+        let t = Torrent::from_bencode_bytes(&bytes).unwrap();
+        assert_eq!(t.length, 150);
+        assert_eq!(t.info_hash, get_sha1(&info_bytes));
+        assert_eq!(
+            t.announce_hashes(),
+            vec![
+                get_sha1(&info_bytes),
+                truncate_v2_hash(&get_sha256(&info_bytes))
+            ]
+        );
+
+        // v1
+        let t = Torrent::from_file(PathBuf::from("tests/Audio_20160422_archive.torrent")).unwrap();
+        assert_eq!(t.info_hash_v2, None);
+        assert_eq!(t.announce_hashes(), vec![t.info_hash]);
+    }
+
+    #[test]
+    fn test_non_canonical_info() {
+        // unsorted keys and an integer with a leading zero, re-encoding it would give another hash
+        let info =
+            b"d4:name4:file6:lengthi016384e12:piece lengthi16384e6:pieces20:aaaaaaaaaaaaaaaaaaaae";
+        let mut bytes = b"d8:announce27:http://tracker.tld/announce4:info".to_vec();
+        bytes.extend_from_slice(info);
+        bytes.push(b'e');
+        let t = Torrent::from_bencode_bytes(&bytes).unwrap();
+        assert_eq!(t.info_hash, get_sha1(info));
+        assert_eq!(t.length, 16384);
+    }
+
+    #[test]
+    fn test_non_utf8() {
+        // the first announce URL is not UTF-8, the name is "中文" in GBK
+        let torrent = |encoding: &[u8], info: &[u8]| {
+            let mut bytes =
+                b"d13:announce-listll8:http://\xffel27:http://tracker.tld/announceee".to_vec();
+            bytes.extend_from_slice(encoding);
+            bytes.extend_from_slice(b"4:info");
+            bytes.extend_from_slice(info);
+            bytes.push(b'e');
+            Torrent::from_bencode_bytes(&bytes).unwrap()
+        };
+        let t = torrent(b"8:encoding3:GBK", b"d6:lengthi1e4:name4:\xd6\xd0\xce\xc4e");
+        assert_eq!(t.name, "中文");
+        assert_eq!(t.encoding.as_deref(), Some("GBK"));
+        assert_eq!(t.urls, vec!["http://tracker.tld/announce"]);
+
+        // `name.utf-8` is preferred
+        let t = torrent(
+            b"8:encoding3:GBK",
+            b"d6:lengthi1e4:name4:\xd6\xd0\xce\xc410:name.utf-86:\xe4\xb8\xad\xe6\x96\x87e",
+        );
+        assert_eq!(t.name, "中文");
+
+        // unknown encoding
+        let t = torrent(b"", b"d6:lengthi1e4:name3:a\xffbe");
+        assert_eq!(t.name, "a\u{fffd}b");
+    }
+
+    #[test]
+    fn test_invalid_announce_list() {
+        // tiers and URLs of the wrong type are skipped
+        let t = Torrent::from_bencode_bytes(
+            b"d13:announce-listli1eli2e27:http://tracker.tld/announceee4:infod6:lengthi1e4:name1:aee",
+        )
+        .unwrap();
+        assert_eq!(t.urls, vec!["http://tracker.tld/announce"]);
+
+        // a negative length is located
+        let error = Torrent::from_bencode_bytes(
+            b"d8:announce27:http://tracker.tld/announce4:infod6:lengthi-1e4:name1:aee",
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("in info.length"), "{error}");
+    }
+
+    #[test]
+    fn test_length_overflow() {
+        let file = |path: &str| format!("d6:lengthi9223372036854775807e4:pathl1:{path}ee");
+        let v1 = format!(
+            "d8:announce27:http://tracker.tld/announce4:infod5:filesl{}{}{}e4:name1:aee",
+            file("a"),
+            file("b"),
+            file("c")
+        );
+        let error = Torrent::from_bencode_bytes(v1.as_bytes()).unwrap_err();
+        assert!(error.to_string().contains("overflows"), "{error}");
+
+        let file = "d0:d6:lengthi9223372036854775807eee";
+        let v2 = format!(
+            "d8:announce27:http://tracker.tld/announce4:infod9:file treed1:a{file}1:b{file}1:c{file}e\
+            12:meta versioni2e4:name1:aee"
+        );
+        let error = Torrent::from_bencode_bytes(v2.as_bytes()).unwrap_err();
+        assert!(error.to_string().contains("overflows"), "{error}");
+    }
+
+    #[test]
+    fn test_from_magnet_v2() {
+        let hash = "1220".to_string() + &"ab".repeat(32);
+        let t = Torrent::from_magnet(&format!(
+            "magnet:?xt=urn:btmh:{hash}&tr=http://tracker.tld/announce"
+        ))
+        .unwrap();
+        assert_eq!(t.info_hash_v2, Some([0xab; 32]));
+        assert_eq!(t.info_hash, [0xab; 20]);
+
+        let t = Torrent::from_magnet(&format!(
+            "magnet:?xt=urn:btih:0123456789abcdef0123456789abcdef01234567&xt=urn:btmh:{hash}\
+            &tr=http://tracker.tld/announce"
+        ))
+        .unwrap();
+        assert_eq!(t.announce_hashes().len(), 2);
+        assert!(
+            Torrent::from_magnet("magnet:?xt=urn:btmh:1114ab&tr=http://tracker.tld/announce")
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_is_torrent_file() {
+        assert!(is_torrent_file(Path::new("/tmp/a.torrent")));
+        assert!(is_torrent_file(Path::new("/tmp/a.MAGNET")));
+        assert!(!is_torrent_file(Path::new("/tmp/a.txt")));
+        assert!(!is_torrent_file(Path::new("/tmp/a")));
+    }
+
+    #[test]
+    fn test_is_unregistered() {
+        let mut t = Torrent::from_magnet(
+            "magnet:?xt=urn:btih:0123456789abcdef0123456789abcdef01234567\
+            &tr=http://a.tld/announce&tr=http://b.tld/announce",
+        )
+        .unwrap();
+        assert!(!t.is_unregistered());
+        let unregistered = TrackerStatus {
+            unregistered: true,
+            ..Default::default()
+        };
+        t.trackers.insert(t.urls[0].clone(), unregistered.clone());
+        // the other tracker is still announced
+        assert!(!t.is_unregistered());
+        t.trackers.insert(t.urls[1].clone(), unregistered);
+        assert!(t.is_unregistered());
+    }
 }
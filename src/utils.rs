@@ -1,3 +1,5 @@
+use sha2::{Digest, Sha256};
+
 pub fn format_bytes(bytes: u32) -> String {
     format_bytes_u64(bytes as u64)
 }
@@ -48,12 +50,92 @@ pub fn percent_encoding(input: &[u8]) -> String {
     encoded_string
 }
 
+/// Lowercase hexadecimal representation of the bytes (i.e. for info hashes)
+pub fn to_hex(input: &[u8]) -> String {
+    let mut result = String::with_capacity(input.len() * 2);
+    for byte in input {
+        result.push_str(&format!("{:02x}", byte));
+    }
+    result
+}
+
+/// Parse hexadecimal bytes, case insensitive
+pub fn from_hex(input: &str) -> Option<Vec<u8>> {
+    if !input.len().is_multiple_of(2) || !input.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..input.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&input[i..i + 2], 16).ok())
+        .collect()
+}
+
+/// Parse an hexadecimal info hash, case insensitive
+pub fn info_hash_from_hex(input: &str) -> Option<[u8; 20]> {
+    from_hex(input)?.try_into().ok()
+}
+
+/// Decode standard base64 (with or without padding), i.e. for HTTP basic authentication
+pub fn base64_decode(input: &str) -> Option<Vec<u8>> {
+    fn value(c: u8) -> Option<u32> {
+        match c {
+            b'A'..=b'Z' => Some((c - b'A') as u32),
+            b'a'..=b'z' => Some((c - b'a' + 26) as u32),
+            b'0'..=b'9' => Some((c - b'0' + 52) as u32),
+            b'+' => Some(62),
+            b'/' => Some(63),
+            _ => None,
+        }
+    }
+    let input = input.trim_end_matches('=').as_bytes();
+    if input.len() % 4 == 1 {
+        return None;
+    }
+    let mut result = Vec::with_capacity(input.len() * 3 / 4);
+    for chunk in input.chunks(4) {
+        let mut buffer: u32 = 0;
+        for (i, c) in chunk.iter().enumerate() {
+            buffer |= value(*c)? << (18 - 6 * i);
+        }
+        let bytes = buffer.to_be_bytes();
+        result.extend_from_slice(&bytes[1..chunk.len()]);
+    }
+    Some(result)
+}
+
+/// Decode RFC 4648 base32 without padding, case insensitive (i.e. for magnet link info hashes)
+pub fn base32_decode(input: &str) -> Option<Vec<u8>> {
+    let mut result = Vec::with_capacity(input.len() * 5 / 8);
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for c in input.trim_end_matches('=').bytes() {
+        let value = match c.to_ascii_uppercase() {
+            c @ b'A'..=b'Z' => c - b'A',
+            c @ b'2'..=b'7' => c - b'2' + 26,
+            _ => return None,
+        };
+        buffer = (buffer << 5) | value as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            result.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+    Some(result)
+}
+
 pub fn get_sha1(input: &[u8]) -> [u8; 20] {
     let mut m = sha1_smol::Sha1::new();
     m.update(input);
     m.digest().bytes()
 }
 
+/// SHA-256, it is used for BitTorrent v2 info hashes
+pub fn get_sha256(input: &[u8]) -> [u8; 32] {
+    Sha256::digest(input).into()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -142,5 +224,66 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_hex() {
+        let hash =
+            *b"\xb5\x07\xc6\x96\x4f\xfa\x3f\xaa\xaa\x1a\xa3\xac\x2d\x42\x2d\x39\xa9\xc9\xe2\x46";
+        assert_eq!(to_hex(&hash), "b507c6964ffa3faaaa1aa3ac2d422d39a9c9e246");
+        assert_eq!(
+            info_hash_from_hex("B507C6964FFA3FAAAA1AA3AC2D422D39A9C9E246"),
+            Some(hash)
+        );
+        assert_eq!(info_hash_from_hex("b507c6"), None);
+        assert_eq!(from_hex("00fF"), Some(vec![0, 255]));
+        assert_eq!(from_hex("+f"), None);
+        assert_eq!(from_hex("abc"), None);
+        assert_eq!(
+            info_hash_from_hex("z507c6964ffa3faaaa1aa3ac2d422d39a9c9e246"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_base64_decode() {
+        assert_eq!(base64_decode("").unwrap(), b"");
+        assert_eq!(base64_decode("Zg==").unwrap(), b"f");
+        assert_eq!(base64_decode("Zm8=").unwrap(), b"fo");
+        assert_eq!(base64_decode("Zm9v").unwrap(), b"foo");
+        assert_eq!(base64_decode("Zm9vYg").unwrap(), b"foob");
+        assert_eq!(
+            base64_decode("dXNlcjpwYXNzd29yZA==").unwrap(),
+            b"user:password"
+        );
+        assert!(base64_decode("Zm9v*").is_none());
+        assert!(base64_decode("Z").is_none());
+    }
+
     // [181, 7, 198, 150, 79, 250, 63, 170, 170, 26, 163, 172, 45, 66, 45, 57, 169, 201, 226, 70] => should be b507c6964ffa3faaaa1aa3ac2d422d39a9c9e246
+
+    #[test]
+    fn test_base32_decode() {
+        assert_eq!(base32_decode("MZXW6YTBOI").unwrap(), b"foobar");
+        assert_eq!(base32_decode("mzxw6ytboi======").unwrap(), b"foobar");
+        assert_eq!(base32_decode(""), Some(Vec::new()));
+        assert_eq!(base32_decode("MZXW1"), None);
+    }
+
+    #[test]
+    fn test_sha256() {
+        assert_eq!(
+            to_hex(&get_sha256(b"")),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            to_hex(&get_sha256(b"abc")),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        // two blocks
+        assert_eq!(
+            to_hex(&get_sha256(
+                b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"
+            )),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+    }
 }
@@ -1,27 +1,75 @@
-use std::path::PathBuf;
-use std::sync::mpsc;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, mpsc};
 use std::time::Duration;
 
+use notify::event::{AccessKind, AccessMode, ModifyKind};
 use notify::{Config, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
 use tokio::sync::Mutex;
+use tokio::time::Instant;
 use tracing::{debug, error, info, warn};
 
 use crate::announcer::tracker::{Event, announce};
-use crate::torrent::Torrent;
-use crate::utils::format_bytes_u64;
-use crate::{CLIENT, TORRENTS};
-
-/// File system event that we care about
-#[derive(Debug, Clone)]
-enum FsEvent {
-    Added(PathBuf),
-    Removed(PathBuf),
+use crate::directory::{
+    NO_URL_ERROR, category_of, is_hidden, list_files, move_done, move_unregistered,
+    quarantine_invalid, without_url,
+};
+use crate::events::{self, BusEvent};
+use crate::torrent::{
+    PAUSED_EXTENSION, Torrent, is_torrent_file, load_file, paused_marker, unregistered_marker,
+};
+use crate::utils::{format_bytes_u64, to_hex};
+use crate::{CLIENT, CONFIG, TORRENTS};
+
+/// Time without event on a path before handling it, to coalesce the events of a copy, a rename or an editor save
+const DEBOUNCE: Duration = Duration::from_secs(1);
+/// Delay before loading again a file that cannot be parsed (i.e. partially written), multiplied by the attempt number
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+/// Number of attempts to parse a file before giving up
+const MAX_ATTEMPTS: u32 = 8;
+
+/// Path with file system events that is waiting to be handled
+#[derive(Debug)]
+struct Pending {
+    due: Instant,
+    /// Number of failed attempts to parse the file
+    attempts: u32,
+}
+
+/// Get the torrent file path if the given path is a pause marker file (i.e. `name.torrent.paused`)
+fn marked_torrent(path: &Path) -> Option<PathBuf> {
+    if path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case(PAUSED_EXTENSION))
+    {
+        let torrent_path = path.with_extension("");
+        if is_torrent_file(&torrent_path) {
+            return Some(torrent_path);
+        }
+    }
+    None
 }
 
-/// Start watching the torrent directory for file changes.
+/// Tells if the event can change a file content or name. The end of a write is used when the platform reports it.
+fn is_relevant(kind: &EventKind) -> bool {
+    matches!(
+        kind,
+        EventKind::Create(_)
+            | EventKind::Remove(_)
+            | EventKind::Modify(ModifyKind::Name(_))
+            | EventKind::Modify(ModifyKind::Data(_))
+            | EventKind::Modify(ModifyKind::Any)
+            | EventKind::Access(AccessKind::Close(AccessMode::Write))
+    )
+}
+
+/// Start watching the torrent directory and its subfolders for file changes.
+///
+/// Events are coalesced by path: a path is handled once there is no event on it for `DEBOUNCE`, then the loaded
+/// torrents are synchronized with the files (see `sync_paths`).
 pub async fn watch_directory(directory: PathBuf) {
     let (sync_tx, sync_rx) = mpsc::channel::<notify::Result<notify::Event>>();
-    let (async_tx, mut async_rx) = tokio::sync::mpsc::unbounded_channel::<FsEvent>();
+    let (async_tx, mut async_rx) = tokio::sync::mpsc::unbounded_channel::<PathBuf>();
 
     let mut watcher = match RecommendedWatcher::new(
         move |res| {
@@ -38,7 +86,7 @@ pub async fn watch_directory(directory: PathBuf) {
         }
     };
 
-    if let Err(e) = watcher.watch(&directory, RecursiveMode::NonRecursive) {
+    if let Err(e) = watcher.watch(&directory, RecursiveMode::Recursive) {
         error!("Cannot watch directory {}: {e}", directory.display());
         return;
     }
@@ -52,24 +100,12 @@ pub async fn watch_directory(directory: PathBuf) {
             match sync_rx.recv() {
                 Ok(Ok(event)) => {
                     debug!("File event: {:?}", event);
+                    if !is_relevant(&event.kind) {
+                        continue;
+                    }
+                    // paths are filtered once the events are coalesced, a folder can be renamed
                     for path in event.paths {
-                        // Only process .torrent files
-                        if !path
-                            .extension()
-                            .is_some_and(|ext| ext.eq_ignore_ascii_case("torrent"))
-                        {
-                            continue;
-                        }
-
-                        let fs_event = match event.kind {
-                            EventKind::Create(_) => Some(FsEvent::Added(path)),
-                            EventKind::Remove(_) => Some(FsEvent::Removed(path)),
-                            _ => None,
-                        };
-
-                        if let Some(e) = fs_event
-                            && async_tx_clone.send(e).is_err()
-                        {
+                        if async_tx_clone.send(path).is_err() {
                             error!("Failed to send event to async channel");
                             return;
                         }
@@ -87,41 +123,349 @@ pub async fn watch_directory(directory: PathBuf) {
     });
 
     // Process events asynchronously
-    while let Some(event) = async_rx.recv().await {
-        match event {
-            FsEvent::Added(path) => {
-                // Small delay to ensure file is fully written
-                info!("File added: {}", path.display());
-                tokio::time::sleep(Duration::from_millis(500)).await;
-                handle_file_added(path).await;
+    let mut pending: HashMap<PathBuf, Pending> = HashMap::new();
+    loop {
+        let next_due = pending.values().map(|p| p.due).min();
+        let received = match next_due {
+            Some(due) => tokio::time::timeout_at(due, async_rx.recv()).await.ok(),
+            None => Some(async_rx.recv().await),
+        };
+        match received {
+            Some(Some(path)) => {
+                // a new event restarts the delay, failed attempts are kept
+                let due = Instant::now() + DEBOUNCE;
+                pending
+                    .entry(path)
+                    .and_modify(|p| p.due = p.due.max(due))
+                    .or_insert(Pending { due, attempts: 0 });
             }
-            FsEvent::Removed(path) => {
-                info!("File removed: {}", path.display());
-                handle_file_removed(path).await;
+            // channel closed
+            Some(None) => break,
+            // no event until the next due path
+            None => {
+                // paths of the same operation (i.e. both paths of a rename) must be handled together
+                let now = Instant::now() + DEBOUNCE / 2;
+                let ready: Vec<(PathBuf, u32)> = pending
+                    .extract_if(|_, p| p.due <= now)
+                    .map(|(path, p)| (path, p.attempts))
+                    .collect();
+                for (path, attempts) in sync_paths(&directory, ready).await {
+                    pending.insert(
+                        path,
+                        Pending {
+                            due: Instant::now() + RETRY_DELAY * attempts,
+                            attempts,
+                        },
+                    );
+                }
             }
         }
     }
 }
 
-async fn handle_file_added(path: PathBuf) {
-    info!("New torrent file detected: {}", path.display());
+/// Get the info hashes of the torrents loaded from the given file (a `.magnet` file can have several torrents)
+pub async fn hashes_from_path(path: &PathBuf) -> Vec<[u8; 20]> {
+    let list = TORRENTS.read().await;
+    let mut hashes = Vec::new();
+    for m in list.iter() {
+        let t = m.lock().await;
+        if t.source_path.as_ref() == Some(path) {
+            hashes.push(t.info_hash);
+        }
+    }
+    hashes
+}
 
-    // Parse the torrent file
-    let torrent = match Torrent::from_file(path.clone()) {
-        Ok(t) => t,
-        Err(e) => {
-            error!("Cannot parse torrent {}: {e}", path.display());
+/// Get the files of the loaded torrents that are in the given folder
+async fn loaded_paths_under(folder: &Path) -> BTreeSet<PathBuf> {
+    let list = TORRENTS.read().await;
+    let mut paths = BTreeSet::new();
+    for m in list.iter() {
+        if let Some(source) = &m.lock().await.source_path
+            && source.starts_with(folder)
+        {
+            paths.insert(source.clone());
+        }
+    }
+    paths
+}
+
+/// Synchronize the loaded torrents with the files at the given paths, it handles:
+/// - added, removed and renamed files (and folders), a renamed torrent keeps its stats and is not announced
+/// - modified files: a torrent with a new info hash is stopped and the new one is started, trackers are updated
+/// - created and removed pause markers
+///
+/// Paths come with the number of failed attempts to parse them. It returns the files that cannot be parsed yet (i.e.
+/// partially written) with their attempt count, to try again later.
+async fn sync_paths(directory: &Path, paths: Vec<(PathBuf, u32)>) -> Vec<(PathBuf, u32)> {
+    let mut files: BTreeMap<PathBuf, u32> = BTreeMap::new();
+    let mut markers: BTreeSet<PathBuf> = BTreeSet::new();
+    for (path, attempts) in paths {
+        if is_hidden(directory, &path) {
+            // i.e. files moved to `.invalid` or `.done`
+            continue;
+        } else if path.is_dir() {
+            // a folder is created or moved in the torrent directory
+            let mut listed = Vec::new();
+            list_files(&path, &mut listed);
+            for file in listed.into_iter().filter(|p| is_torrent_file(p)) {
+                files.entry(file).or_insert(0);
+            }
+        } else if is_torrent_file(&path) {
+            files.insert(path, attempts);
+        } else if let Some(torrent_path) = marked_torrent(&path) {
+            markers.insert(torrent_path);
+        } else if !path.exists() {
+            // a folder may be removed or moved out
+            for file in loaded_paths_under(&path).await {
+                files.entry(file).or_insert(0);
+            }
+        }
+    }
+
+    // parse the files that exist, a removed file has no torrent
+    let mut retries = Vec::new();
+    let mut found: Vec<(PathBuf, Vec<Torrent>)> = Vec::new();
+    for (path, attempts) in files {
+        if !path.exists() {
+            found.push((path, Vec::new()));
+            continue;
+        }
+        let mut torrents = Vec::new();
+        let mut errors = Vec::new();
+        for result in load_file(&path) {
+            match result {
+                Ok(t) => torrents.push(t),
+                Err(e) => errors.push(e),
+            }
+        }
+        if !errors.is_empty() {
+            if attempts + 1 < MAX_ATTEMPTS {
+                debug!("Cannot parse {} yet: {}", path.display(), errors[0]);
+                retries.push((path, attempts + 1));
+                continue;
+            }
+            for e in &errors {
+                error!("Cannot parse torrent {}: {e}", path.display());
+            }
+            if torrents.is_empty() && hashes_from_path(&path).await.is_empty() {
+                let errors: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+                quarantine_invalid(directory, &path, &errors.join("\n"));
+                continue;
+            }
+        }
+        if without_url(&torrents) && hashes_from_path(&path).await.is_empty() {
+            warn!("Skipping torrent {}: {}", path.display(), AddError::NoUrl);
+            quarantine_invalid(directory, &path, NO_URL_ERROR);
+            continue;
+        }
+        found.push((path, torrents));
+    }
+
+    // torrents that are no longer in their file, they may be in another one (renamed)
+    let mut gone: Vec<([u8; 20], PathBuf)> = Vec::new();
+    for (path, torrents) in &found {
+        for hash in hashes_from_path(path).await {
+            if !torrents.iter().any(|t| t.info_hash == hash) {
+                gone.push((hash, path.clone()));
+            }
+        }
+    }
+    let mut moved: Vec<([u8; 20], PathBuf)> = Vec::new();
+    for (hash, path) in gone {
+        let is_moved = found
+            .iter()
+            .any(|(_, torrents)| torrents.iter().any(|t| t.info_hash == hash));
+        if is_moved {
+            moved.push((hash, path));
+        } else {
+            info!("Torrent file removed or changed: {}", path.display());
+            remove_torrent(&hash).await;
+            if !path.exists() {
+                // do not keep the markers without their torrent
+                let _ = tokio::fs::remove_file(paused_marker(&path)).await;
+                let _ = tokio::fs::remove_file(unregistered_marker(&path)).await;
+            }
+        }
+    }
+
+    for (path, torrents) in found {
+        let loaded = hashes_from_path(&path).await;
+        let category = category_of(directory, &path);
+        for mut torrent in torrents {
+            torrent.category = category.clone();
+            if loaded.contains(&torrent.info_hash) {
+                update_torrent(torrent).await;
+            } else if let Some((_, old_path)) = moved.iter().find(|(h, _)| h == &torrent.info_hash)
+            {
+                info!(
+                    "Torrent file moved: {} -> {}",
+                    old_path.display(),
+                    path.display()
+                );
+                for marker in [paused_marker, unregistered_marker] {
+                    let old_marker = marker(old_path);
+                    if !old_path.exists() && old_marker.exists() {
+                        let new_marker = marker(&path);
+                        if let Err(e) = tokio::fs::rename(&old_marker, &new_marker).await {
+                            warn!("Cannot move marker {}: {e}", old_marker.display());
+                        }
+                    }
+                }
+                update_torrent(torrent).await;
+            } else {
+                info!("New torrent file detected: {}", path.display());
+                torrent.paused = paused_marker(&path).exists();
+                if let Err(e) = add_torrent(torrent).await {
+                    warn!("Skipping torrent {}: {e}", path.display());
+                }
+            }
+        }
+    }
+
+    for path in markers {
+        let paused = paused_marker(&path).exists();
+        for hash in hashes_from_path(&path).await {
+            set_paused(&hash, paused).await;
+        }
+    }
+    retries
+}
+
+/// Update a loaded torrent from a new version of its file or after a rename, it keeps its stats
+async fn update_torrent(torrent: Torrent) {
+    let list = TORRENTS.read().await;
+    for m in list.iter() {
+        let mut t = m.lock().await;
+        if t.info_hash == torrent.info_hash {
+            if t.urls != torrent.urls {
+                info!("Trackers updated: {}", torrent.name);
+            }
+            t.name = torrent.name;
+            t.urls = torrent.urls;
+            t.length = torrent.length;
+            t.private = torrent.private;
+            t.encoding = torrent.encoding;
+            t.source_path = torrent.source_path;
+            t.category = torrent.category;
             return;
         }
+    }
+}
+
+/// Pause (announce STOPPED) or resume (announce STARTED) a torrent.
+///
+/// The paused state is saved with a marker file next to the torrent file (`name.torrent.paused`) so it survives
+/// restarts. It is shared by the file watcher, the control file and the API. Returns false if the torrent is not found.
+pub async fn set_paused(info_hash: &[u8; 20], paused: bool) -> bool {
+    let Some(torrent) = find_torrent(info_hash).await else {
+        return false;
+    };
+    // only the torrent is locked during the announce
+    let mut t = torrent.lock().await;
+    if t.paused == paused {
+        return true;
+    }
+    let event = if paused {
+        if CLIENT.read().await.is_some() {
+            announce(&mut t, Some(Event::Stopped)).await;
+        }
+        t.paused = true;
+        info!("Torrent paused: {}", t.name);
+        BusEvent::TorrentPaused {
+            info_hash: to_hex(&t.info_hash),
+            name: t.name.clone(),
+        }
+    } else {
+        t.paused = false;
+        // the trackers are tried again, i.e. when the torrent was registered again
+        if t.trackers.values().any(|s| s.unregistered) {
+            for status in t.trackers.values_mut() {
+                status.unregistered = false;
+            }
+            t.save_unregistered();
+        }
+        if CLIENT.read().await.is_some() {
+            announce(&mut t, Some(Event::Started)).await;
+        }
+        info!("Torrent resumed: {}", t.name);
+        BusEvent::TorrentResumed {
+            info_hash: to_hex(&t.info_hash),
+            name: t.name.clone(),
+        }
     };
+    events::publish(event);
+    let source = t.source_path.clone();
+    drop(t);
 
+    // the watcher is notified of the marker change but the torrent is already in the right state.
+    // The marker of a file with several magnet links would change all of them, so it is not written.
+    let Some(source) = source else {
+        return true;
+    };
+    if shared_source_count(&TORRENTS.read().await, &source).await == 1 {
+        let marker = paused_marker(&source);
+        let result = if paused {
+            tokio::fs::write(&marker, b"").await
+        } else {
+            tokio::fs::remove_file(&marker).await
+        };
+        if let Err(e) = result
+            && e.kind() != std::io::ErrorKind::NotFound
+        {
+            warn!("Cannot update pause marker {}: {e}", marker.display());
+        }
+    }
+    true
+}
+
+/// Torrent with the given info hash, it can be locked after releasing the list
+pub async fn find_torrent(info_hash: &[u8; 20]) -> Option<Arc<Mutex<Torrent>>> {
+    let list = TORRENTS.read().await;
+    for m in list.iter() {
+        if &m.lock().await.info_hash == info_hash {
+            return Some(Arc::clone(m));
+        }
+    }
+    None
+}
+
+/// Number of loaded torrents coming from the given file
+async fn shared_source_count(list: &[Arc<Mutex<Torrent>>], path: &PathBuf) -> usize {
+    let mut count = 0;
+    for m in list.iter() {
+        if m.lock().await.source_path.as_ref() == Some(path) {
+            count += 1;
+        }
+    }
+    count
+}
+
+/// Reasons why a torrent cannot be added to the list
+#[derive(Debug, PartialEq)]
+pub enum AddError {
+    /// No supported tracker URL (DHT only or unsupported schemes)
+    NoUrl,
+    /// A torrent with the same info hash is already loaded
+    Duplicate,
+}
+
+impl std::fmt::Display for AddError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AddError::NoUrl => write!(f, "no URL (DHT or not supported URLs)"),
+            AddError::Duplicate => write!(f, "a torrent with the same hash already exists"),
+        }
+    }
+}
+
+/// Add a torrent to the list and announce it with the STARTED event, unless it is paused.
+///
+/// It is shared by the file watcher and the control API.
+pub async fn add_torrent(torrent: Torrent) -> Result<(), AddError> {
     // Check if torrent has URLs
     if torrent.urls.is_empty() {
-        warn!(
-            "Skipping torrent {} because there is no URL (DHT or not supported URLs)",
-            path.display()
-        );
-        return;
+        return Err(AddError::NoUrl);
     }
 
     // Check for duplicates
@@ -129,124 +473,181 @@ async fn handle_file_added(path: PathBuf) {
         let list = TORRENTS.read().await;
         for m in list.iter() {
             let t = m.lock().await;
-            if t.info_hash_urlencoded == torrent.info_hash_urlencoded {
-                warn!("Torrent with same hash already exists: {}", torrent.name);
-                return;
+            if t.info_hash == torrent.info_hash {
+                return Err(AddError::Duplicate);
             }
         }
     }
 
     let name = torrent.name.clone();
-    let info_hash = torrent.info_hash_urlencoded.clone();
+    let paused = torrent.paused;
+    let added_event = BusEvent::torrent_added(&torrent);
 
     // Add to the list
-    {
-        let mut list = TORRENTS.write().await;
-        list.push(Mutex::new(torrent));
+    let torrent = Arc::new(Mutex::new(torrent));
+    TORRENTS.write().await.push(Arc::clone(&torrent));
+    events::publish(added_event);
+
+    // Announce with STARTED event, a paused torrent is announced when resumed
+    if !paused && CLIENT.read().await.is_some() {
+        let mut t = torrent.lock().await;
+        announce(&mut t, Some(Event::Started)).await;
+        info!(
+            "Added and announced torrent: {} (interval: {}s)",
+            name, t.interval
+        );
     }
+    Ok(())
+}
 
-    // Announce with STARTED event
-    if CLIENT.read().await.is_some() {
-        let list = TORRENTS.read().await;
-        // Find the torrent we just added (last one with matching hash)
-        for m in list.iter().rev() {
-            let mut t = m.lock().await;
-            if t.info_hash_urlencoded == info_hash {
-                announce(&mut t, Some(Event::Started)).await;
-                info!(
-                    "Added and announced torrent: {} (interval: {}s)",
-                    name, t.interval
-                );
-                break;
-            }
+/// Handle a finished torrent (stop rule reached or not registered on its trackers): it is removed and its file moved to
+/// the `.done` folder, or to the `.unregistered` one with the failure reasons, if `quarantine_done` is enabled,
+/// otherwise it is paused. The file is kept if other torrents are loaded from it (magnet links).
+pub async fn finish_torrent(info_hash: &[u8; 20]) {
+    let Some(config) = CONFIG.get().filter(|c| c.quarantine_done) else {
+        set_paused(info_hash, true).await;
+        return;
+    };
+    if let Some(torrent) = remove_torrent(info_hash).await
+        && let Some(path) = &torrent.source_path
+        && hashes_from_path(path).await.is_empty()
+    {
+        if torrent.is_unregistered() {
+            move_unregistered(&config.torrent_dir, path, &torrent.unregistered_reason());
+        } else {
+            move_done(&config.torrent_dir, path);
         }
     }
 }
 
-async fn handle_file_removed(path: PathBuf) {
-    info!("Torrent file removed: {}", path.display());
-
-    // Find and remove the torrent
-    let mut removed_info: Option<(String, u64, u16, u16, u16)> = None;
-    let mut removed_hash: Option<String> = None;
-
+/// Announce the STOPPED event and remove the torrent from the list.
+///
+/// It is shared by the file watcher and the control API. Returns the removed torrent if it was found.
+pub async fn remove_torrent(info_hash: &[u8; 20]) -> Option<Torrent> {
+    // Announce STOPPED before removing, only the torrent is locked
     {
-        let list = TORRENTS.read().await;
-        for m in list.iter() {
-            let t = m.lock().await;
-            // First try to match by source path (most reliable)
-            let matches = if let Some(ref source) = t.source_path {
-                source == &path
-            } else {
-                // Fallback: match by filename stem vs torrent name
-                path.file_stem().is_some_and(|stem| {
-                    let filename = stem.to_string_lossy();
-                    t.name == filename.as_ref() || t.name.starts_with(filename.as_ref())
-                })
-            };
-
-            if matches {
-                removed_hash = Some(t.info_hash_urlencoded.clone());
-                removed_info = Some((
-                    t.name.clone(),
-                    t.uploaded,
-                    t.seeders,
-                    t.leechers,
-                    t.error_count,
-                ));
+        let torrent = find_torrent(info_hash).await?;
+        let mut t = torrent.lock().await;
+        if !t.paused && CLIENT.read().await.is_some() {
+            announce(&mut t, Some(Event::Stopped)).await;
+        }
+    }
+
+    // Remove from list
+    let removed = {
+        let mut list = TORRENTS.write().await;
+        let mut index = None;
+        for (i, m) in list.iter().enumerate() {
+            if &m.lock().await.info_hash == info_hash {
+                index = Some(i);
                 break;
             }
         }
+        index.map(|i| list.remove(i))
+    };
+    let removed = match removed {
+        Some(m) => Some(m.lock().await.clone()),
+        None => None,
+    };
+
+    // Print stats
+    if let Some(t) = &removed {
+        events::publish(BusEvent::torrent_removed(t));
+        info!(
+            "Removed torrent \"{}\": uploaded={}, seeders={}, leechers={}, errors={}",
+            t.name,
+            format_bytes_u64(t.uploaded),
+            t.seeders,
+            t.leechers,
+            t.error_count
+        );
     }
+    removed
+}
 
-    if let Some(hash) = removed_hash {
-        // Announce STOPPED before removing
-        if CLIENT.read().await.is_some() {
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_relevant() {
+        use notify::event::{CreateKind, MetadataKind, RenameMode};
+        assert!(is_relevant(&EventKind::Create(CreateKind::File)));
+        assert!(is_relevant(&EventKind::Modify(ModifyKind::Name(
+            RenameMode::Both
+        ))));
+        assert!(!is_relevant(&EventKind::Modify(ModifyKind::Metadata(
+            MetadataKind::Any
+        ))));
+        assert!(!is_relevant(&EventKind::Access(AccessKind::Read)));
+    }
+
+    #[tokio::test]
+    async fn test_sync_paths() {
+        let mut dir = std::env::temp_dir();
+        dir.push("ratioup-test-watcher");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("category")).unwrap();
+        let data = std::fs::read("tests/Audio_20160422_archive.torrent").unwrap();
+        let hash = Torrent::from_bencode_bytes(&data).unwrap().info_hash;
+
+        // added
+        let first = dir.join("a.torrent");
+        std::fs::write(&first, &data).unwrap();
+        assert!(sync_paths(&dir, vec![(first.clone(), 0)]).await.is_empty());
+        assert_eq!(hashes_from_path(&first).await, vec![hash]);
+
+        // renamed to a category folder, it keeps the torrent
+        let second = dir.join("category").join("b.torrent");
+        std::fs::rename(&first, &second).unwrap();
+        let paths = vec![(first.clone(), 0), (second.clone(), 0)];
+        assert!(sync_paths(&dir, paths).await.is_empty());
+        assert!(hashes_from_path(&first).await.is_empty());
+        assert_eq!(hashes_from_path(&second).await, vec![hash]);
+        {
             let list = TORRENTS.read().await;
+            let mut category = None;
             for m in list.iter() {
-                let mut t = m.lock().await;
-                if t.info_hash_urlencoded == hash {
-                    announce(&mut t, Some(Event::Stopped)).await;
-                    break;
+                let t = m.lock().await;
+                if t.info_hash == hash {
+                    category = t.category.clone();
                 }
             }
+            assert_eq!(category.as_deref(), Some("category"));
         }
 
-        // Remove from list
-        {
-            let mut list = TORRENTS.write().await;
-            list.retain(|m| {
-                // We need to check without async, so we use try_lock
-                if let Ok(t) = m.try_lock() {
-                    t.info_hash_urlencoded != hash
-                } else {
-                    true // Keep if we can't lock (shouldn't happen)
-                }
-            });
-        }
+        // partially written, it is tried again
+        let partial = dir.join("partial.torrent");
+        std::fs::write(&partial, &data[..data.len() / 2]).unwrap();
+        assert_eq!(
+            sync_paths(&dir, vec![(partial.clone(), 2)]).await,
+            vec![(partial, 3)]
+        );
 
-        // Print stats
-        if let Some((name, uploaded, seeders, leechers, errors)) = removed_info {
-            info!(
-                "Removed torrent \"{}\": uploaded={}, seeders={}, leechers={}, errors={}",
-                name,
-                format_bytes_u64(uploaded),
-                seeders,
-                leechers,
-                errors
-            );
-        }
-    } else {
-        warn!(
-            "Could not find torrent matching removed file: {}",
-            path.display()
+        // removed with its folder
+        std::fs::remove_dir_all(dir.join("category")).unwrap();
+        assert!(
+            sync_paths(&dir, vec![(dir.join("category"), 0)])
+                .await
+                .is_empty()
         );
+        assert!(hashes_from_path(&second).await.is_empty());
+        let _ = std::fs::remove_dir_all(&dir);
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use std::path::Path;
+    #[test]
+    fn test_marked_torrent() {
+        assert_eq!(
+            marked_torrent(Path::new("/tmp/test.torrent.paused")),
+            Some(PathBuf::from("/tmp/test.torrent"))
+        );
+        assert_eq!(
+            marked_torrent(Path::new("/tmp/test.magnet.paused")),
+            Some(PathBuf::from("/tmp/test.magnet"))
+        );
+        assert_eq!(marked_torrent(Path::new("/tmp/test.paused")), None);
+        assert_eq!(marked_torrent(Path::new("/tmp/test.torrent")), None);
+    }
 
     #[test]
     fn test_torrent_extension_check() {